@@ -3,7 +3,9 @@ select distinct
   first_value(object_id) over (partition by detail_id) as track_id,
   title as title,
   artist as artist,
+  creator as album_artist,
   album as album,
+  genre as genre,
   album_art as album_art_id,
   duration as duration,
   date as date,
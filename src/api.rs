@@ -1,151 +1,284 @@
 use crate::db::Track;
 
-use reqwest::Url;
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::time::Duration;
 
-pub struct Api<'a> {
-    url: &'a str,
-    src_url: &'a str,
+#[derive(Clone)]
+pub struct Api {
+    url: String,
+    src_url: String,
     client: reqwest::blocking::Client,
 }
 
+/// Why a request to the streamer failed
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request couldn't be sent or the response couldn't be read, e.g. the streamer is off
+    Transport(reqwest::Error),
+    /// The streamer answered with an error status
+    Status(StatusCode),
+    /// The response body wasn't the JSON we expected
+    Json(serde_json::Error),
+    /// The response parsed, but didn't contain what we needed
+    UnexpectedPayload(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Transport(e) => write!(f, "Couldn't reach the streamer: {}", e),
+            ApiError::Status(status) => write!(f, "Streamer returned {}", status),
+            ApiError::Json(e) => write!(f, "Invalid response from the streamer: {}", e),
+            ApiError::UnexpectedPayload(msg) => {
+                write!(f, "Unexpected response from the streamer: {}", msg)
+            }
+        }
+    }
+}
+
+impl Error for ApiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApiError::Transport(e) => Some(e),
+            ApiError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        ApiError::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::Json(e)
+    }
+}
+
 enum PowerState {
     On,
     Suspend,
 }
 
-impl<'a> Api<'a> {
-    pub fn new(url: &'a str, src_url: &'a str) -> Self {
+impl Api {
+    pub fn new(url: &str, src_url: &str) -> Self {
         Api {
-            url,
-            src_url,
+            url: url.to_string(),
+            src_url: src_url.to_string(),
             client: reqwest::blocking::Client::new(),
         }
     }
 
-    fn get_playlist(&self) -> Result<Playlist, reqwest::Error> {
-        self.client
-            .get(format!("{}/inputs/playqueue", self.url).as_str())
-            .send()?
-            .json::<Playlist>()
+    fn send(&self, request: RequestBuilder) -> Result<Response, ApiError> {
+        let response = request.send()?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(ApiError::Status(response.status()))
+        }
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, ApiError> {
+        let body = self
+            .send(self.client.get(format!("{}{}", self.url, path).as_str()))?
+            .text()?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    pub fn get_playlist(&self) -> Result<Playlist, ApiError> {
+        self.get_json("/inputs/playqueue")
     }
 
     // Start playing from the current entry or beginning of the playlist
-    pub fn play(&self) {
-        match self.get_playlist() {
-            Err(e) => eprint!("{}", e),
-            Ok(playlist) => {
-                let entry = if playlist.current.is_some() {
-                    playlist.current
-                } else {
-                    playlist
-                        .children
-                        .and_then(|children| children.get(0).map(|e| e.ussi.clone()))
-                };
-
-                if let Some(e) = entry {
-                    self.play_entry(e)
-                }
-            }
+    pub fn play(&self) -> Result<(), ApiError> {
+        let playlist = self.get_playlist()?;
+        let entry = if playlist.current.is_some() {
+            playlist.current
+        } else {
+            playlist
+                .children
+                .and_then(|children| children.first().map(|e| e.ussi.clone()))
+        };
+
+        match entry {
+            Some(e) => self.play_entry(e),
+            None => Ok(()),
         }
     }
 
-    fn play_entry(&self, entry: String) {
-        let url = Url::parse_with_params(
-            &format!("{}/inputs/playqueue", self.url),
-            &[("current", entry)],
-        )
-        .unwrap();
-        let _response = self.client.put(url).send().map_err(|e| eprintln!("{}", e));
+    /// Jump to a playlist entry, identified by its `ussi`, and start playing it
+    pub fn play_entry(&self, entry: String) -> Result<(), ApiError> {
+        let request = self
+            .client
+            .put(format!("{}/inputs/playqueue", self.url).as_str())
+            .query(&[("current", entry)]);
+        self.send(request).map(|_| ())
     }
 
-    pub fn queue_track(&self, track: &Track) {
-        let _resp = self
-            .client
-            .post(format!("{}/inputs/playqueue?where=end&clear=false", self.url).as_str())
-            .json(&PlaylistTrack::from_track(self.src_url, track))
-            .send()
-            .map_err(|e| eprintln!("{}", e));
+    // Append tracks to the playlist in a single request
+    pub fn queue_tracks(&self, tracks: &[&Track]) -> Result<(), ApiError> {
+        self.add_tracks(tracks, "where=end&clear=false")
+    }
+
+    // Insert tracks after the currently playing entry
+    pub fn queue_tracks_next(&self, tracks: &[&Track]) -> Result<(), ApiError> {
+        self.add_tracks(tracks, "where=next&clear=false")
     }
 
-    pub fn clear_playlist(&self) {
-        let _resp = self
+    // Replace the whole playlist with the tracks and start playing the first one
+    pub fn replace_and_play(&self, tracks: &[&Track]) -> Result<(), ApiError> {
+        self.add_tracks(tracks, "clear=true&current=0&play=true")
+    }
+
+    // Append entries, such as the ones from a saved playlist
+    pub fn queue_entries(&self, entries: &[PlaylistEntry]) -> Result<(), ApiError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let body: Vec<PlaylistTrack> = entries.iter().map(PlaylistTrack::from_entry).collect();
+        self.post_tracks(&body, "where=end&clear=false")
+    }
+
+    // The streamer can't remove or move entries in its queue, so it's edited by
+    // replacing the whole thing. Jumping to `current` starts it from the beginning.
+    pub fn replace_playlist(
+        &self,
+        entries: &[PlaylistEntry],
+        current: Option<usize>,
+    ) -> Result<(), ApiError> {
+        if entries.is_empty() {
+            return self.clear_playlist();
+        }
+        let body: Vec<PlaylistTrack> = entries.iter().map(PlaylistTrack::from_entry).collect();
+        match current {
+            Some(i) => self.post_tracks(&body, &format!("clear=true&current={}&play=true", i)),
+            None => self.post_tracks(&body, "clear=true"),
+        }
+    }
+
+    fn add_tracks(&self, tracks: &[&Track], params: &str) -> Result<(), ApiError> {
+        if tracks.is_empty() {
+            return Ok(());
+        }
+        let body: Vec<PlaylistTrack> = tracks
+            .iter()
+            .map(|t| PlaylistTrack::from_track(&self.src_url, t))
+            .collect();
+        self.post_tracks(&body, params)
+    }
+
+    fn post_tracks(&self, body: &[PlaylistTrack], params: &str) -> Result<(), ApiError> {
+        let request = self
             .client
-            .post(format!("{}/inputs/playqueue?clear=true", self.url).as_str())
-            .send()
-            .map_err(|e| eprintln!("{}", e));
+            .post(format!("{}/inputs/playqueue?{}", self.url, params).as_str())
+            .json(&body);
+        self.send(request).map(|_| ())
     }
 
-    pub fn get_volume(&self) -> Result<u8, Box<dyn Error>> {
-        let res: HashMap<String, String> = self
+    pub fn clear_playlist(&self) -> Result<(), ApiError> {
+        let request = self
             .client
-            .get(format!("{}/levels", self.url).as_str())
-            .send()?
-            .json()?;
+            .post(format!("{}/inputs/playqueue?clear=true", self.url).as_str());
+        self.send(request).map(|_| ())
+    }
+
+    pub fn get_now_playing(&self) -> Result<NowPlaying, ApiError> {
+        self.get_json("/nowplaying")
+    }
+
+    pub fn get_status(&self) -> Status {
+        Status {
+            now_playing: self.get_now_playing().ok(),
+            volume: self.get_volume().ok(),
+        }
+    }
+
+    pub fn get_volume(&self) -> Result<u8, ApiError> {
+        let res: HashMap<String, String> = self.get_json("/levels")?;
         let volume = res
             .get("volume")
-            .ok_or("'volume' not found in levels object")?
+            .ok_or_else(|| ApiError::UnexpectedPayload("'volume' not found in levels".into()))?;
+        volume
             .parse::<u8>()
-            .unwrap();
-        Ok(volume)
+            .map_err(|_| ApiError::UnexpectedPayload(format!("invalid volume '{}'", volume)))
     }
 
-    pub fn incr_volume(&self, current: Option<u8>) -> Option<u8> {
-        let current_volume = current.or_else(|| self.get_volume().ok())?;
+    pub fn incr_volume(&self) -> Result<u8, ApiError> {
+        let current_volume = self.get_volume()?;
 
         if current_volume < 100 {
             self.set_volume(current_volume + 1)
         } else {
-            current
+            Ok(current_volume)
         }
     }
 
-    pub fn decr_volume(&self, current: Option<u8>) -> Option<u8> {
-        let current_volume = current.or_else(|| self.get_volume().ok())?;
+    pub fn decr_volume(&self) -> Result<u8, ApiError> {
+        let current_volume = self.get_volume()?;
 
         if current_volume > 0 {
             self.set_volume(current_volume - 1)
         } else {
-            current
+            Ok(current_volume)
         }
     }
 
-    fn set_volume(&self, volume: u8) -> Option<u8> {
-        self.client
-            .put(format!("{}/levels?volume={}", self.url, volume).as_str())
-            .send()
-            .ok()
-            .map(|_| volume)
+    pub fn set_volume(&self, volume: u8) -> Result<u8, ApiError> {
+        let request = self
+            .client
+            .put(format!("{}/levels?volume={}", self.url, volume).as_str());
+        self.send(request).map(|_| volume)
+    }
+
+    pub fn toggle_play_pause(&self) -> Result<(), ApiError> {
+        self.now_playing_cmd("cmd=playpause")
+    }
+
+    pub fn stop(&self) -> Result<(), ApiError> {
+        self.now_playing_cmd("cmd=stop")
+    }
+
+    pub fn next(&self) -> Result<(), ApiError> {
+        self.now_playing_cmd("cmd=next")
+    }
+
+    pub fn previous(&self) -> Result<(), ApiError> {
+        self.now_playing_cmd("cmd=prev")
     }
 
-    pub fn toggle_play_pause(&self) {
-        let _result = self
+    fn now_playing_cmd(&self, params: &str) -> Result<(), ApiError> {
+        let request = self
             .client
-            .get(format!("{}/nowplaying?cmd=playpause", self.url).as_str())
-            .send()
-            .map_err(|e| eprintln!("{}", e));
+            .get(format!("{}/nowplaying?{}", self.url, params).as_str());
+        self.send(request).map(|_| ())
     }
 
-    pub fn power_on(&self) {
-        self.power(PowerState::On);
+    pub fn power_on(&self) -> Result<(), ApiError> {
+        self.power(PowerState::On)
     }
 
-    pub fn suspend(&self) {
-        self.power(PowerState::Suspend);
+    pub fn suspend(&self) -> Result<(), ApiError> {
+        self.power(PowerState::Suspend)
     }
 
-    fn power(&self, power: PowerState) {
+    fn power(&self, power: PowerState) -> Result<(), ApiError> {
         let system = match power {
             PowerState::On => "on",
             PowerState::Suspend => "lona",
         };
-        let _response = self
+        let request = self
             .client
-            .put(format!("{}/power?system={}", self.url, system).as_str())
-            .send()
-            .map_err(|e| eprintln!("{}", e));
+            .put(format!("{}/power?system={}", self.url, system).as_str());
+        self.send(request).map(|_| ())
     }
 }
 
@@ -163,25 +296,74 @@ pub struct PlaylistTrack<'a> {
     serverId: &'a str,
     uri: String,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct PlaylistEntry {
-    name: String,
-    artistName: String,
-    albumName: String,
+    pub name: String,
+    pub artistName: String,
+    pub albumName: String,
     class: String,
-    ussi: String,
+    pub ussi: String,
     artwork: String,
     track: String,
     mimeType: String,
     serverId: String,
-    uri: String,
+    pub uri: String,
 }
 
 #[derive(Debug, Deserialize)]
-struct Playlist {
-    current: Option<String>,
-    children: Option<Vec<PlaylistEntry>>,
+pub struct Playlist {
+    pub current: Option<String>,
+    pub children: Option<Vec<PlaylistEntry>>,
+}
+
+/// What the streamer is currently doing. The streamer sends every value as a string.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(non_snake_case)]
+pub struct NowPlaying {
+    pub title: Option<String>,
+    pub artistName: Option<String>,
+    pub albumName: Option<String>,
+    transportState: Option<String>,
+    transportPosition: Option<String>,
+    duration: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransportState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl NowPlaying {
+    pub fn state(&self) -> Option<TransportState> {
+        match self.transportState.as_deref()? {
+            "1" | "stopped" => Some(TransportState::Stopped),
+            "2" | "playing" => Some(TransportState::Playing),
+            "3" | "paused" => Some(TransportState::Paused),
+            _ => None,
+        }
+    }
+
+    /// How far into the current track playback is
+    pub fn position(&self) -> Option<Duration> {
+        let millis = self.transportPosition.as_ref()?.parse().ok()?;
+        Some(Duration::from_millis(millis))
+    }
+
+    /// The length of the current track
+    pub fn duration(&self) -> Option<Duration> {
+        let secs = self.duration.as_ref()?.parse().ok()?;
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// The latest state polled from the streamer. `None` values couldn't be fetched.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub now_playing: Option<NowPlaying>,
+    pub volume: Option<u8>,
 }
 
 impl<'a> PlaylistTrack<'a> {
@@ -199,6 +381,22 @@ impl<'a> PlaylistTrack<'a> {
             uri: track_url(dlna_url, track),
         }
     }
+
+    // Entries have everything needed to add them again, apart from the genre
+    fn from_entry(entry: &'a PlaylistEntry) -> Self {
+        PlaylistTrack {
+            name: entry.name.as_str(),
+            artistName: entry.artistName.as_str(),
+            albumName: entry.albumName.as_str(),
+            class: entry.class.as_str(),
+            artwork: entry.artwork.clone(),
+            genre: "",
+            track: entry.track.as_str(),
+            mimeType: entry.mimeType.as_str(),
+            serverId: entry.serverId.as_str(),
+            uri: entry.uri.clone(),
+        }
+    }
 }
 
 fn artwork_url(dlna_url: &str, track: &Track) -> String {
@@ -224,6 +422,6 @@ fn track_url(dlna_url: &str, track: &Track) -> String {
     let mut url = dlna_url.to_string();
     url.push_str("/MediaItems/");
     url.push_str(&track.id.to_string());
-    url.push_str(&extension);
+    url.push_str(extension);
     url
 }
@@ -1,228 +1,1039 @@
-use crate::api;
+use crate::api::{Playlist, PlaylistEntry, Status, TransportState};
+use crate::config::{self, Config};
 use crate::db::Track;
-use crate::widgets::StatefulList;
+use crate::keys::{Action, Bindings};
+use crate::m3u;
+use crate::playlists::PlaylistStore;
+use crate::search::SearchIndex;
+use crate::widgets::{StatefulList, StatefulTable};
+use crate::worker::{Command, Position, Reply};
 
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use termion::event::Key;
 use tui::widgets::TableState;
 
-#[derive(Debug, PartialEq)]
+/// How many changes to the queue can be undone
+const UNDO_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Pane {
-    ARTISTS,
-    TRACKS,
+    Artists,
+    Albums,
+    Tracks,
+    Playlist,
+    /// The tracks matching the last search, shown in place of the tracks pane
+    Results,
+}
+
+/// How the library is browsed: by track artist, or by album grouped by album artist
+#[derive(Debug, PartialEq)]
+pub enum View {
+    Artists,
+    Albums,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Mode {
     Normal,
     SearchInput,
+    /// Typing the name to save the queue as
+    PlaylistName,
+    /// Choosing a saved playlist to add to the queue
+    PlaylistPicker,
+    /// Typing the M3U or PLS file to add to the queue
+    ImportPath,
+    /// Typing the file to write the queue to as M3U8
+    ExportPath,
+    /// Showing what each key does
+    Help,
+    /// Asking whether to go ahead with an action
+    Confirm(Action),
 }
 
 pub struct App<'a> {
     pub mode: Mode,
+    pub view: View,
     pub should_quit: bool,
     pub artists: StatefulList<Artist<'a>>,
-    pub albums: StatefulList<&'a str>,
+    pub albums: StatefulList<Album<'a>>,
+    pub all_albums: StatefulTable<Album<'a>>,
     pub tracks: Vec<&'a Track>,
     pub search_input: String,
-    current_match: Option<usize>,
+    search_index: SearchIndex,
+    /// Indexes into `all_tracks` of the tracks matching the last search, best first
+    search_results: Vec<usize>,
+    pub results_state: TableState,
+    before_search: Option<Selection>,
     all_tracks: &'a [Track],
     current_pane: Pane,
     pub track_list_state: TableState,
-    naim_api: api::Api<'a>,
-    volume: Option<u8>,
+    pub playlist: Vec<PlaylistEntry>,
+    pub current_entry: Option<String>,
+    pub playlist_state: TableState,
+    /// Changes to the queue sent to the streamer which haven't been answered yet
+    pending_edits: usize,
+    /// Whether any of those were left for the streamer to make, rather than
+    /// being made here first, so the queue on screen is out of date
+    queue_outdated: bool,
+    /// The queue as the streamer last reported it
+    confirmed_queue: QueueSnapshot,
+    /// The queue as it was before each change, most recent last
+    undo: VecDeque<QueueSnapshot>,
+    /// Text typed into the playlist name and file popups
+    pub input: String,
+    pub saved_playlists: StatefulList<String>,
+    /// How many lines the help has been scrolled down by
+    pub help_scroll: u16,
+    store: PlaylistStore,
+    pub status: Option<Status>,
+    /// The last error, shown until the next key press
+    pub message: Option<String>,
+    commands: Sender<Command>,
+    pub volume: Option<u8>,
+    /// Volume changes sent to the streamer which haven't been answered yet
+    pending_volume: usize,
+    /// Whether to select the playing entry once the refreshed playlist arrives
+    select_current_on_refresh: bool,
+    pub bindings: Bindings,
+    /// Actions which ask before they run
+    confirm: HashSet<Action>,
+    pub colors: config::Colors,
+    queue: config::Queue,
+}
+
+/// Where the selection was when a search started, to go back to if it's cancelled.
+struct Selection {
+    pane: Pane,
+    library: LibrarySelection,
+    search_results: Vec<usize>,
+    result: Option<usize>,
+}
+
+#[derive(Clone, Default)]
+struct QueueSnapshot {
+    entries: Vec<PlaylistEntry>,
+    /// The ussi of the entry that was playing
+    current: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+struct LibrarySelection {
+    artist: Option<usize>,
+    album: Option<usize>,
+    all_albums: Option<usize>,
+    track: Option<usize>,
 }
 
 pub struct Artist<'a> {
     pub name: &'a str,
 }
 
+pub struct Album<'a> {
+    pub artist: &'a str,
+    pub name: &'a str,
+    pub year: Option<&'a str>,
+    pub track_count: usize,
+}
+
 impl<'a> App<'a> {
-    pub fn new(naim_api: api::Api<'a>, tracks: &'a [Track]) -> App<'a> {
+    pub fn new(
+        commands: Sender<Command>,
+        store: PlaylistStore,
+        config: &Config,
+        tracks: &'a [Track],
+    ) -> App<'a> {
         let mut artists = tracks.iter().map(|t| t.artist.as_str()).collect::<Vec<_>>();
-        let mut albums = tracks.iter().map(|t| t.album.as_str()).collect::<Vec<_>>();
         artists.sort_unstable();
         artists.dedup();
         let artists = artists
             .iter()
             .map(|name| Artist { name })
             .collect::<Vec<_>>();
-        albums.sort_unstable();
-        albums.dedup();
 
-        let current_artist = artists.get(0).unwrap();
-        let current_tracks: Vec<&Track> = tracks
-            .iter()
-            .filter(|t| t.artist == current_artist.name)
-            .collect();
+        let all_albums = collect_albums(tracks.iter(), Track::album_artist_or_artist);
 
-        App {
+        let mut app = App {
             mode: Mode::Normal,
+            view: View::Artists,
             all_tracks: tracks,
             should_quit: false,
             artists: StatefulList::with_items(artists),
-            albums: StatefulList::with_items(albums),
-            tracks: current_tracks,
+            albums: StatefulList::with_items(Vec::new()),
+            all_albums: StatefulTable::with_items(all_albums),
+            tracks: Vec::new(),
             search_input: String::new(),
-            current_match: None,
-            current_pane: Pane::ARTISTS,
+            search_index: SearchIndex::new(tracks),
+            search_results: Vec::new(),
+            results_state: TableState::default(),
+            before_search: None,
+            current_pane: Pane::Artists,
             track_list_state: TableState::default(),
-            naim_api,
+            playlist: Vec::new(),
+            current_entry: None,
+            playlist_state: TableState::default(),
+            pending_edits: 0,
+            queue_outdated: false,
+            confirmed_queue: QueueSnapshot::default(),
+            undo: VecDeque::new(),
+            input: String::new(),
+            saved_playlists: StatefulList::with_items(Vec::new()),
+            help_scroll: 0,
+            store,
+            status: None,
+            message: None,
+            commands,
             volume: None,
-        }
+            pending_volume: 0,
+            select_current_on_refresh: false,
+            bindings: config.bindings.clone(),
+            confirm: config.confirmed.clone(),
+            colors: config.colors,
+            queue: config.queue,
+        };
+        app.set_albums();
+        app.set_tracks();
+        app.refresh_playlist();
+        app
     }
 
-    pub fn on_up(&mut self) {
-        if self.current_pane == Pane::ARTISTS {
-            self.artists.previous(1);
-            self.set_tracks();
-        } else {
-            self.track_list_state
-                .select(match self.track_list_state.selected() {
-                    Some(pos) => {
-                        if pos == 0 {
-                            Some(self.tracks.len() - 1)
-                        } else {
-                            Some(pos - 1)
-                        }
-                    }
-                    None => Some(0),
-                })
+    fn on_up(&mut self) {
+        match self.current_pane {
+            Pane::Artists => self.previous_artist(1),
+            Pane::Albums => self.previous_album(1),
+            Pane::Tracks => select_previous(&mut self.track_list_state, self.tracks.len()),
+            Pane::Playlist => select_previous(&mut self.playlist_state, self.playlist.len()),
+            Pane::Results => self.previous_search_match(),
         }
     }
 
-    pub fn on_down(&mut self) {
-        if self.current_pane == Pane::ARTISTS {
-            self.artists.next(1);
-            self.set_tracks();
-        } else {
-            self.select_next_track();
+    fn on_down(&mut self) {
+        match self.current_pane {
+            Pane::Artists => self.next_artist(1),
+            Pane::Albums => self.next_album(1),
+            Pane::Tracks => self.select_next_track(),
+            Pane::Playlist => select_next(&mut self.playlist_state, self.playlist.len()),
+            Pane::Results => self.next_search_match(),
         }
     }
 
     fn select_next_track(&mut self) {
-        self.track_list_state
-            .select(match self.track_list_state.selected() {
-                Some(pos) => {
-                    if pos == self.tracks.len() - 1 {
-                        Some(0)
-                    } else {
-                        Some(pos + 1)
-                    }
-                }
-                None => Some(0),
-            })
+        select_next(&mut self.track_list_state, self.tracks.len());
     }
 
-    pub fn on_page_up(&mut self) {
-        if self.current_pane == Pane::ARTISTS {
-            self.artists.previous(10);
-            self.set_tracks();
+    fn on_page_up(&mut self) {
+        match self.current_pane {
+            Pane::Artists => self.previous_artist(10),
+            Pane::Albums => self.previous_album(10),
+            _ => {}
+        }
+    }
+
+    fn on_page_down(&mut self) {
+        match self.current_pane {
+            Pane::Artists => self.next_artist(10),
+            Pane::Albums => self.next_album(10),
+            _ => {}
+        }
+    }
+
+    /// Go to the first or last item of the current pane.
+    fn select_edge(&mut self, last: bool) {
+        let edge = |len: usize| match len {
+            0 => None,
+            len if last => Some(len - 1),
+            _ => Some(0),
+        };
+        match self.current_pane {
+            Pane::Artists => {
+                self.artists.state.select(edge(self.artists.items.len()));
+                self.set_albums();
+                self.set_tracks();
+            }
+            Pane::Albums => {
+                match self.view {
+                    View::Artists => self.albums.state.select(edge(self.albums.items.len())),
+                    View::Albums => self
+                        .all_albums
+                        .state
+                        .select(edge(self.all_albums.items.len())),
+                }
+                self.set_tracks();
+            }
+            Pane::Tracks => self.track_list_state.select(edge(self.tracks.len())),
+            Pane::Playlist => self.playlist_state.select(edge(self.playlist.len())),
+            Pane::Results => {
+                self.results_state.select(edge(self.search_results.len()));
+                self.show_search_match();
+            }
         }
     }
 
-    pub fn on_page_down(&mut self) {
-        if self.current_pane == Pane::ARTISTS {
-            self.artists.next(10);
-            self.set_tracks();
+    fn previous_artist(&mut self, n: usize) {
+        self.artists.previous(n);
+        self.set_albums();
+        self.set_tracks();
+    }
+
+    fn next_artist(&mut self, n: usize) {
+        self.artists.next(n);
+        self.set_albums();
+        self.set_tracks();
+    }
+
+    fn previous_album(&mut self, n: usize) {
+        match self.view {
+            View::Artists => self.albums.previous(n),
+            View::Albums => self.all_albums.previous(n),
         }
+        self.set_tracks();
     }
 
-    pub fn on_backspace(&mut self) {
-        if self.mode == Mode::SearchInput {
-            self.search_input.pop();
+    fn next_album(&mut self, n: usize) {
+        match self.view {
+            View::Artists => self.albums.next(n),
+            View::Albums => self.all_albums.next(n),
         }
+        self.set_tracks();
     }
 
-    fn current_track(&self) -> Option<&Track> {
+    fn current_track(&self) -> Option<&'a Track> {
+        if self.current_pane == Pane::Results {
+            return self.current_result();
+        }
         self.track_list_state
             .selected()
             .map(|i| *self.tracks.get(i).unwrap())
         //            .map(|t| PlaylistTrack::from_track(self.src_url, t))
     }
 
-    pub fn on_key(&mut self, c: char) {
+    fn current_result(&self) -> Option<&'a Track> {
+        let all_tracks = self.all_tracks;
+        self.results_state
+            .selected()
+            .and_then(|n| self.search_results.get(n))
+            .map(|&i| &all_tracks[i])
+    }
+
+    pub fn showing_results(&self) -> bool {
+        self.current_pane == Pane::Results
+    }
+
+    /// The tracks matching the last search, best first.
+    pub fn search_results(&self) -> impl Iterator<Item = &'a Track> + '_ {
+        let all_tracks = self.all_tracks;
+        self.search_results.iter().map(move |&i| &all_tracks[i])
+    }
+
+    /// Where the selected search result is in the results, e.g. "match 3 of 17".
+    pub fn match_counter(&self) -> Option<String> {
+        self.results_state
+            .selected()
+            .map(|n| format!("match {} of {}", n + 1, self.search_results.len()))
+    }
+
+    pub fn on_key(&mut self, key: Key) {
+        self.message = None;
         match self.mode {
-            Mode::Normal => match c {
-                'q' => {
-                    self.should_quit = true;
-                }
-                '\t' => {
-                    self.current_pane = match self.current_pane {
-                        Pane::ARTISTS => {
-                            if self.track_list_state.selected().is_none() {
-                                self.track_list_state.select(Some(0));
-                            };
-                            Pane::TRACKS
+            Mode::Normal => {
+                if let Some(action) = self.bindings.feed(key) {
+                    self.run_action(action);
+                }
+            }
+            Mode::SearchInput => match key {
+                Key::Char('\n') => {
+                    self.mode = Mode::Normal;
+                    self.before_search = None;
+                    if self.search_results.is_empty() {
+                        self.message = Some(format!("No tracks match '{}'", self.search_input));
+                    } else {
+                        self.current_pane = Pane::Results;
+                    }
+                }
+                Key::Char(c) => {
+                    self.search_input.push(c);
+                    self.update_search();
+                }
+                Key::Backspace => {
+                    self.search_input.pop();
+                    self.update_search();
+                }
+                // Cancelling a search goes back to where the selection was before it
+                Key::Esc => {
+                    self.mode = Mode::Normal;
+                    if let Some(selection) = self.before_search.take() {
+                        self.restore_selection(selection);
+                    }
+                }
+                _ => {}
+            },
+            Mode::PlaylistName | Mode::ImportPath | Mode::ExportPath => match key {
+                Key::Char('\n') => match mem::replace(&mut self.mode, Mode::Normal) {
+                    Mode::PlaylistName => self.save_playlist(),
+                    Mode::ImportPath => self.import_playlist(),
+                    _ => self.export_playlist(),
+                },
+                Key::Char(c) => self.input.push(c),
+                Key::Backspace => {
+                    self.input.pop();
+                }
+                Key::Esc => self.mode = Mode::Normal,
+                _ => {}
+            },
+            // The picker is a list like any other, so it takes the same bindings
+            Mode::PlaylistPicker => match self.bindings.feed(key) {
+                Some(Action::Up) => self.saved_playlists.previous(1),
+                Some(Action::Down) => self.saved_playlists.next(1),
+                Some(Action::PageUp) => self.saved_playlists.previous(10),
+                Some(Action::PageDown) => self.saved_playlists.next(10),
+                Some(Action::Select) => {
+                    self.mode = Mode::Normal;
+                    self.load_selected_playlist();
+                }
+                Some(Action::Close) | Some(Action::Quit) => self.mode = Mode::Normal,
+                _ => {}
+            },
+            // Scrolled by the ui, which knows how much of it fits
+            Mode::Help => match self.bindings.feed(key) {
+                Some(Action::Up) => self.help_scroll = self.help_scroll.saturating_sub(1),
+                Some(Action::Down) => self.help_scroll = self.help_scroll.saturating_add(1),
+                Some(Action::PageUp) => self.help_scroll = self.help_scroll.saturating_sub(10),
+                Some(Action::PageDown) => self.help_scroll = self.help_scroll.saturating_add(10),
+                Some(Action::First) => self.help_scroll = 0,
+                Some(Action::Last) => self.help_scroll = u16::MAX,
+                Some(Action::Help) | Some(Action::Close) | Some(Action::Quit) => {
+                    self.mode = Mode::Normal
+                }
+                _ => {}
+            },
+            // Anything but yes leaves things as they were
+            Mode::Confirm(action) => {
+                self.mode = Mode::Normal;
+                if let Key::Char('y') | Key::Char('Y') = key {
+                    self.perform_action(action);
+                }
+            }
+        }
+    }
+
+    fn run_action(&mut self, action: Action) {
+        if self.confirm.contains(&action) {
+            self.mode = Mode::Confirm(action);
+        } else {
+            self.perform_action(action);
+        }
+    }
+
+    fn perform_action(&mut self, action: Action) {
+        match action {
+            Action::Up => self.on_up(),
+            Action::Down => self.on_down(),
+            Action::PageUp => self.on_page_up(),
+            Action::PageDown => self.on_page_down(),
+            Action::First => self.select_edge(false),
+            Action::Last => self.select_edge(true),
+            Action::Quit => {
+                self.should_quit = true;
+            }
+            Action::SwitchPane => {
+                self.current_pane = match self.current_pane {
+                    Pane::Artists => Pane::Albums,
+                    Pane::Albums => {
+                        if self.track_list_state.selected().is_none() {
+                            self.track_list_state.select(Some(0));
+                        };
+                        Pane::Tracks
+                    }
+                    Pane::Tracks => {
+                        self.track_list_state.select(None);
+                        self.select_current_on_refresh = true;
+                        self.refresh_playlist();
+                        Pane::Playlist
+                    }
+                    Pane::Playlist => {
+                        self.playlist_state.select(None);
+                        match self.view {
+                            View::Artists => Pane::Artists,
+                            View::Albums => Pane::Albums,
                         }
-                        Pane::TRACKS => {
-                            self.track_list_state.select(None);
-                            Pane::ARTISTS
+                    }
+                    Pane::Results => Pane::Tracks,
+                }
+            }
+            Action::Select => match self.current_pane {
+                Pane::Playlist => self.play_selected_entry(),
+                Pane::Results => {
+                    if let Some(track) = self.current_result() {
+                        self.add_tracks(vec![track], self.queue.add_to);
+                        if self.queue.advance {
+                            self.next_search_match();
                         }
                     }
                 }
-                '\n' => {
+                _ => {
                     if let Some(track) = self.current_track() {
-                        self.naim_api.queue_track(track);
-                        self.select_next_track();
+                        self.add_tracks(vec![track], self.queue.add_to);
+                        if self.queue.advance {
+                            self.select_next_track();
+                        }
                     }
                 }
-                'n' => self.next_search_match(),
-                'p' => self.naim_api.play(),
-                ' ' => self.naim_api.toggle_play_pause(),
-                '+' => self.volume = self.naim_api.incr_volume(self.volume),
-                '-' => self.volume = self.naim_api.decr_volume(self.volume),
-                'P' => self.naim_api.power_on(),
-                'S' => self.naim_api.suspend(),
-                'C' => self.naim_api.clear_playlist(),
-                '/' => self.mode = Mode::SearchInput,
-                _ => {}
             },
-            Mode::SearchInput => {
-                if c == '\n' {
-                    self.mode = Mode::Normal;
-                    self.current_match = None;
-                    self.next_search_match();
-                } else {
-                    self.search_input.push(c);
+            Action::QueueNext => {
+                if let Some(track) = self.current_track() {
+                    self.add_tracks(vec![track], Position::Next);
+                }
+            }
+            Action::ReplaceQueue => {
+                if let Some(track) = self.current_track() {
+                    self.add_tracks(vec![track], Position::Replace);
                 }
             }
+            Action::QueueAlbum => self.add_tracks(self.tracks.clone(), self.queue.add_to),
+            Action::QueueArtist => self.add_tracks(self.artist_tracks(), self.queue.add_to),
+            Action::ToggleView => self.toggle_view(),
+            Action::NextMatch => self.next_search_match(),
+            Action::PreviousMatch => self.previous_search_match(),
+            Action::RevealMatch if self.current_pane == Pane::Results => self.reveal_result(),
+            Action::Close if self.current_pane == Pane::Results => {
+                self.current_pane = Pane::Tracks;
+            }
+            Action::Play => self.send(Command::Play),
+            Action::PlayPause => self.send(Command::TogglePlayPause),
+            Action::Stop => self.send(Command::Stop),
+            Action::NextTrack => self.send(Command::Next),
+            Action::PreviousTrack => self.send(Command::Previous),
+            Action::VolumeUp => match self.volume {
+                Some(volume) => self.set_volume(volume.saturating_add(1).min(100)),
+                None => self.send_volume(Command::VolumeUp),
+            },
+            Action::VolumeDown => match self.volume {
+                Some(volume) => self.set_volume(volume.saturating_sub(1)),
+                None => self.send_volume(Command::VolumeDown),
+            },
+            Action::PowerOn => self.send(Command::PowerOn),
+            Action::Suspend => self.send(Command::Suspend),
+            Action::ClearQueue => {
+                self.remember_queue();
+                self.send_queue_change(Command::ClearPlaylist);
+            }
+            Action::Undo => self.undo_queue_change(),
+            Action::RemoveEntry if self.current_pane == Pane::Playlist => {
+                self.remove_selected_entry()
+            }
+            Action::MoveEntryUp if self.current_pane == Pane::Playlist => {
+                self.move_selected_entry(-1)
+            }
+            Action::MoveEntryDown if self.current_pane == Pane::Playlist => {
+                self.move_selected_entry(1)
+            }
+            Action::Search => {
+                self.before_search = Some(self.selection());
+                self.search_input.clear();
+                self.mode = Mode::SearchInput;
+            }
+            Action::SavePlaylist => self.prompt(Mode::PlaylistName),
+            Action::OpenPlaylist => self.open_playlist_picker(),
+            Action::ImportPlaylist => self.prompt(Mode::ImportPath),
+            Action::ExportPlaylist => self.prompt(Mode::ExportPath),
+            Action::Help => {
+                self.help_scroll = 0;
+                self.mode = Mode::Help;
+            }
+            _ => {}
         }
     }
 
-    fn set_tracks(&mut self) {
-        if self.current_pane == Pane::TRACKS {
+    fn prompt(&mut self, mode: Mode) {
+        self.input.clear();
+        self.mode = mode;
+    }
+
+    fn save_playlist(&mut self) {
+        if let Err(e) = self.store.save(&self.input, &self.playlist) {
+            self.message = Some(format!("Couldn't save playlist: {}", e));
+        }
+    }
+
+    fn open_playlist_picker(&mut self) {
+        match self.store.list() {
+            Ok(names) if names.is_empty() => self.message = Some("No saved playlists".to_string()),
+            Ok(names) => {
+                self.saved_playlists = StatefulList::with_items(names);
+                self.mode = Mode::PlaylistPicker;
+            }
+            Err(e) => self.message = Some(format!("Couldn't list playlists: {}", e)),
+        }
+    }
+
+    /// Add the chosen saved playlist to the end of the queue.
+    fn load_selected_playlist(&mut self) {
+        let name = match self.saved_playlists.state.selected() {
+            Some(i) => self.saved_playlists.items[i].clone(),
+            None => return,
+        };
+        match self.store.load(&name) {
+            Ok(entries) => {
+                self.remember_queue();
+                self.send_queue_change(Command::QueueEntries(entries));
+            }
+            Err(e) => self.message = Some(format!("Couldn't load playlist '{}': {}", name, e)),
+        }
+    }
+
+    /// Pass a request on to the worker thread. The outcome arrives later as a `Reply`.
+    fn send(&mut self, command: Command) {
+        self.try_send(command);
+    }
+
+    // The same, saying whether the command reached the worker so a reply can be expected
+    fn try_send(&mut self, command: Command) -> bool {
+        let sent = self.commands.send(command).is_ok();
+        if !sent {
+            self.message = Some("The streamer connection has stopped".to_string());
+        }
+        sent
+    }
+
+    // Send a change the streamer makes to its queue. Until it's answered the queue
+    // on screen is stale, so it can't be edited.
+    fn send_queue_change(&mut self, command: Command) {
+        if self.try_send(command) {
+            self.pending_edits += 1;
+            self.queue_outdated = true;
+        }
+    }
+
+    fn send_volume(&mut self, command: Command) {
+        if self.try_send(command) {
+            self.pending_volume += 1;
+        }
+    }
+
+    fn add_tracks(&mut self, tracks: Vec<&Track>, position: Position) {
+        if !tracks.is_empty() {
+            self.remember_queue();
+            let tracks = tracks.into_iter().cloned().collect();
+            self.send_queue_change(Command::AddTracks(tracks, position));
+        }
+    }
+
+    // Change the volume straight away, so repeated presses don't each wait for the streamer
+    fn set_volume(&mut self, volume: u8) {
+        self.volume = Some(volume);
+        self.send_volume(Command::SetVolume(volume));
+    }
+
+    /// Queue the tracks from the playlist file named in the input, reporting any it couldn't find.
+    fn import_playlist(&mut self) {
+        let import = match m3u::import(&input_path(&self.input), self.all_tracks) {
+            Ok(import) => import,
+            Err(e) => {
+                self.message = Some(e.to_string());
+                return;
+            }
+        };
+        if let Some(first) = import.unmatched.first() {
+            self.message = Some(format!(
+                "{} of {} entries not found, starting with {}",
+                import.unmatched.len(),
+                import.unmatched.len() + import.tracks.len(),
+                first
+            ));
+        }
+        self.add_tracks(import.tracks, Position::End);
+    }
+
+    fn export_playlist(&mut self) {
+        if let Err(e) = m3u::export(&input_path(&self.input), &self.playlist) {
+            self.message = Some(e.to_string());
+        }
+    }
+
+    pub fn on_api_reply(&mut self, reply: Reply) {
+        match reply {
+            // Until every edit is answered the local queue is ahead of the streamer's
+            Reply::Playlist(playlist) if self.pending_edits == 0 => self.set_playlist(playlist),
+            Reply::Playlist(_) => {}
+            Reply::Edited(result) => {
+                self.pending_edits -= 1;
+                if self.pending_edits == 0 {
+                    self.queue_outdated = false;
+                }
+                match result {
+                    Ok(playlist) if self.pending_edits == 0 => self.set_playlist(playlist),
+                    Ok(_) => {}
+                    Err(e) => {
+                        self.message = Some(e.to_string());
+                        if self.pending_edits == 0 {
+                            self.refresh_playlist();
+                        }
+                    }
+                }
+            }
+            Reply::Volume(result) => {
+                self.pending_volume -= 1;
+                match result {
+                    // Later presses have already moved the volume on
+                    Ok(volume) if self.pending_volume == 0 => self.volume = Some(volume),
+                    Ok(_) => {}
+                    Err(e) => self.message = Some(e.to_string()),
+                }
+            }
+            Reply::Status(status) => {
+                // A poll sent before a volume change would undo it on screen
+                if status.volume.is_some() && self.pending_volume == 0 {
+                    self.volume = status.volume;
+                }
+                self.status = Some(status);
+            }
+            Reply::Error(e) => self.message = Some(e.to_string()),
+        }
+    }
+
+    fn refresh_playlist(&mut self) {
+        self.send(Command::RefreshPlaylist);
+    }
+
+    fn set_playlist(&mut self, playlist: Playlist) {
+        self.current_entry = playlist.current;
+        self.playlist = playlist.children.unwrap_or_default();
+        self.confirmed_queue = QueueSnapshot {
+            entries: self.playlist.clone(),
+            current: self.current_entry.clone(),
+        };
+        if self.select_current_on_refresh {
+            self.select_current_on_refresh = false;
+            self.select_current_entry();
+        } else {
+            self.clamp_playlist_selection();
+        }
+    }
+
+    fn remove_selected_entry(&mut self) {
+        let i = match self.playlist_state.selected() {
+            Some(i) if i < self.playlist.len() && !self.waiting_for_queue() => i,
+            _ => return,
+        };
+        self.remember_queue();
+        let mut entries = self.playlist.clone();
+        let removed = entries.remove(i);
+        // Carry on with the following entry if the current one is removed
+        let current = if Some(&removed.ussi) == self.current_entry.as_ref() {
+            Some(i).filter(|&i| i < entries.len())
+        } else {
+            self.current_index(&entries)
+        };
+        let current = current.filter(|_| self.is_playing());
+        self.edit_playlist(entries, current);
+        self.clamp_playlist_selection();
+    }
+
+    /// Move the selected entry up (negative) or down the queue, keeping it selected.
+    fn move_selected_entry(&mut self, offset: isize) {
+        let i = match self.playlist_state.selected() {
+            Some(i) if i < self.playlist.len() => i,
+            _ => return,
+        };
+        let target = i as isize + offset;
+        if target < 0 || target as usize >= self.playlist.len() || self.waiting_for_queue() {
+            return;
+        }
+        self.remember_queue();
+        let mut entries = self.playlist.clone();
+        let entry = entries.remove(i);
+        entries.insert(target as usize, entry);
+        let current = self.current_index(&entries).filter(|_| self.is_playing());
+        self.edit_playlist(entries, current);
+        self.playlist_state.select(Some(target as usize));
+    }
+
+    /// Keep a copy of the queue before changing it, so the change can be undone.
+    ///
+    /// The copy is of the queue the streamer last reported rather than the one
+    /// on screen. Changes made before the streamer has answered the previous
+    /// one aren't recorded, so undoing them all goes back to the last queue
+    /// the streamer confirmed.
+    fn remember_queue(&mut self) {
+        if self.pending_edits > 0 {
             return;
         }
-        let index = self.artists.state.selected().unwrap();
-        let current_artist = self.artists.items.get(index).unwrap();
-        let new_tracks: Vec<&Track> = self
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(self.confirmed_queue.clone());
+    }
+
+    /// Put back the queue from before the last change, going back to the entry
+    /// that was current if something's playing.
+    fn undo_queue_change(&mut self) {
+        if self.waiting_for_queue() {
+            return;
+        }
+        let snapshot = match self.undo.pop_back() {
+            Some(snapshot) => snapshot,
+            None => {
+                self.message = Some("Nothing to undo".to_string());
+                return;
+            }
+        };
+        self.current_entry = snapshot.current;
+        let current = self.current_index(&snapshot.entries);
+        let playing = current.filter(|_| self.is_playing());
+        self.edit_playlist(snapshot.entries, playing);
+        if let (Pane::Playlist, Some(i)) = (self.current_pane, current) {
+            self.playlist_state.select(Some(i));
+        }
+    }
+
+    // Edits are made to the queue on screen, so they have to wait for the streamer
+    // to report any changes it's been left to make
+    fn waiting_for_queue(&mut self) -> bool {
+        if self.queue_outdated {
+            self.message = Some("Waiting for the streamer's queue".to_string());
+        }
+        self.queue_outdated
+    }
+
+    fn current_index(&self, entries: &[PlaylistEntry]) -> Option<usize> {
+        entries
+            .iter()
+            .position(|e| Some(&e.ussi) == self.current_entry.as_ref())
+    }
+
+    // Show the edit straight away and rebuild the streamer's queue to match,
+    // jumping to the `current` entry, which starts it playing.
+    fn edit_playlist(&mut self, entries: Vec<PlaylistEntry>, current: Option<usize>) {
+        self.playlist = entries.clone();
+        let command = Command::EditPlaylist { entries, current };
+        if self.try_send(command) {
+            self.pending_edits += 1;
+        }
+    }
+
+    // Jumping back to the current entry after an edit would start playing it, so
+    // that's only done if it was already playing
+    fn is_playing(&self) -> bool {
+        self.status
+            .as_ref()
+            .and_then(|s| s.now_playing.as_ref())
+            .and_then(|np| np.state())
+            == Some(TransportState::Playing)
+    }
+
+    fn clamp_playlist_selection(&mut self) {
+        if let Some(i) = self.playlist_state.selected() {
+            if self.playlist.is_empty() {
+                self.playlist_state.select(None);
+            } else {
+                self.playlist_state
+                    .select(Some(i.min(self.playlist.len() - 1)));
+            }
+        }
+    }
+
+    fn play_selected_entry(&mut self) {
+        let entry = self
+            .playlist_state
+            .selected()
+            .and_then(|i| self.playlist.get(i));
+        if let Some(entry) = entry {
+            let ussi = entry.ussi.clone();
+            self.send_queue_change(Command::PlayEntry(ussi));
+        }
+    }
+
+    fn select_current_entry(&mut self) {
+        let position = self
+            .playlist
+            .iter()
+            .position(|e| Some(&e.ussi) == self.current_entry.as_ref());
+        let first = if self.playlist.is_empty() {
+            None
+        } else {
+            Some(0)
+        };
+        self.playlist_state.select(position.or(first));
+    }
+
+    fn current_artist(&self) -> Option<&'a str> {
+        self.artists
+            .state
+            .selected()
+            .and_then(|i| self.artists.items.get(i))
+            .map(|a| a.name)
+    }
+
+    fn current_album(&self) -> Option<&Album<'a>> {
+        match self.view {
+            View::Artists => self
+                .albums
+                .state
+                .selected()
+                .and_then(|i| self.albums.items.get(i)),
+            View::Albums => self
+                .all_albums
+                .state
+                .selected()
+                .and_then(|i| self.all_albums.items.get(i)),
+        }
+    }
+
+    /// The artist a track is listed under in the current view.
+    fn view_artist(&self, track: &'a Track) -> &'a str {
+        match self.view {
+            View::Artists => &track.artist,
+            View::Albums => track.album_artist_or_artist(),
+        }
+    }
+
+    /// All tracks of an album in disc and track order.
+    fn album_tracks(&self, album: &Album) -> Vec<&'a Track> {
+        let mut tracks: Vec<&Track> = self
             .all_tracks
             .iter()
-            .filter(|t| t.artist == current_artist.name)
+            .filter(|t| t.album == album.name && self.view_artist(t) == album.artist)
             .collect();
+        sort_by_position(&mut tracks);
+        tracks
+    }
+
+    /// All tracks of the selected artist, album by album as they are listed.
+    fn artist_tracks(&self) -> Vec<&'a Track> {
+        match self.view {
+            View::Artists => self
+                .albums
+                .items
+                .iter()
+                .flat_map(|album| self.album_tracks(album))
+                .collect(),
+            View::Albums => {
+                let artist = match self.current_album() {
+                    Some(album) => album.artist,
+                    None => return Vec::new(),
+                };
+                self.all_albums
+                    .items
+                    .iter()
+                    .filter(|album| album.artist == artist)
+                    .flat_map(|album| self.album_tracks(album))
+                    .collect()
+            }
+        }
+    }
+
+    /// Rebuild the album list for the selected artist, oldest first.
+    fn set_albums(&mut self) {
+        let artist = match self.current_artist() {
+            Some(artist) => artist,
+            None => return,
+        };
+        let tracks = self.all_tracks.iter().filter(|t| t.artist == artist);
+        let albums = collect_albums(tracks, |t| &t.artist);
+        self.albums = StatefulList::with_items(albums);
+    }
 
-        self.tracks = new_tracks;
+    /// Show the tracks of the selected album.
+    fn set_tracks(&mut self) {
+        self.tracks = match self.current_album() {
+            Some(album) => self.album_tracks(album),
+            None => Vec::new(),
+        };
         self.track_list_state.select(None);
     }
 
+    /// Switch between browsing by artist and by album, keeping the current album.
+    fn toggle_view(&mut self) {
+        let shown = self.tracks.first().copied();
+        self.view = match self.view {
+            View::Artists => View::Albums,
+            View::Albums => View::Artists,
+        };
+        self.current_pane = Pane::Albums;
+        match shown {
+            Some(track) => self.select_album_of(track),
+            None => self.set_tracks(),
+        }
+    }
+
+    /// Search again as the query is typed, showing the best match in the tracks pane.
+    fn update_search(&mut self) {
+        self.search_results = self.search_index.search(&self.search_input);
+        self.results_state.select(None);
+        if self.search_results.is_empty() {
+            if let Some(library) = self.before_search.as_ref().map(|s| s.library) {
+                self.restore_library_selection(library);
+            }
+        } else {
+            self.current_pane = Pane::Tracks;
+            self.next_search_match();
+        }
+    }
+
+    fn selection(&self) -> Selection {
+        Selection {
+            pane: self.current_pane,
+            library: LibrarySelection {
+                artist: self.artists.state.selected(),
+                album: self.albums.state.selected(),
+                all_albums: self.all_albums.state.selected(),
+                track: self.track_list_state.selected(),
+            },
+            search_results: self.search_results.clone(),
+            result: self.results_state.selected(),
+        }
+    }
+
+    fn restore_selection(&mut self, selection: Selection) {
+        self.restore_library_selection(selection.library);
+        self.current_pane = selection.pane;
+        self.search_results = selection.search_results;
+        self.results_state.select(selection.result);
+    }
+
+    fn restore_library_selection(&mut self, library: LibrarySelection) {
+        self.artists.state.select(library.artist);
+        self.set_albums();
+        self.albums.state.select(library.album);
+        self.all_albums.state.select(library.all_albums);
+        self.set_tracks();
+        self.track_list_state.select(library.track);
+    }
+
+    /// Select the next of the search results, going back to the best one after the last.
     fn next_search_match(&mut self) {
-        let start = self.current_match.map(|n| n + 1).unwrap_or(0);
+        if !self.search_results.is_empty() {
+            select_next(&mut self.results_state, self.search_results.len());
+            self.show_search_match();
+        }
+    }
 
-        for i in start..self.all_tracks.len() {
-            let track = &self.all_tracks[i];
+    fn previous_search_match(&mut self) {
+        if !self.search_results.is_empty() {
+            select_previous(&mut self.results_state, self.search_results.len());
+            self.show_search_match();
+        }
+    }
 
-            if track.artist.contains(&self.search_input)
-                || track.album.contains(&self.search_input)
-                || track.title.contains(&self.search_input)
-            {
-                self.current_match = Some(i);
-                self.select_track(track);
-                break;
-            }
+    // Keep the library panes on the selected result, ready for when the results are closed
+    fn show_search_match(&mut self) {
+        if let Some(track) = self.current_result() {
+            self.select_track(track);
         }
     }
 
-    fn select_track(&mut self, track: &Track) {
-        self.select_artist(&track.artist);
+    /// Close the results and go to the selected result's artist.
+    fn reveal_result(&mut self) {
+        self.current_pane = match self.view {
+            View::Artists => Pane::Artists,
+            View::Albums => Pane::Albums,
+        };
+    }
+
+    fn select_track(&mut self, track: &'a Track) {
+        self.select_album_of(track);
         for i in 0..self.tracks.len() {
             if self.tracks[i] == track {
                 self.track_list_state.select(Some(i));
@@ -230,6 +1041,32 @@ impl<'a> App<'a> {
         }
     }
 
+    fn select_album_of(&mut self, track: &'a Track) {
+        let artist = self.view_artist(track);
+        if let View::Artists = self.view {
+            self.select_artist(artist);
+        }
+        if let Some(album) = self.current_album() {
+            if album.artist == artist && album.name == track.album {
+                return;
+            }
+        }
+        let matches = |a: &Album| a.artist == artist && a.name == track.album;
+        match self.view {
+            View::Artists => {
+                if let Some(i) = self.albums.items.iter().position(matches) {
+                    self.albums.state.select(Some(i));
+                }
+            }
+            View::Albums => {
+                if let Some(i) = self.all_albums.items.iter().position(matches) {
+                    self.all_albums.state.select(Some(i));
+                }
+            }
+        }
+        self.set_tracks();
+    }
+
     fn select_artist(&mut self, artist: &str) {
         // Check if current artist matches first
         if let Some(i) = self.artists.state.selected() {
@@ -243,8 +1080,69 @@ impl<'a> App<'a> {
                 break;
             }
         }
+        self.set_albums();
         self.set_tracks();
     }
 
     pub fn on_tick(&mut self) {}
 }
+
+// Typed paths can start with "~/" as they would in the shell
+fn input_path(input: &str) -> PathBuf {
+    match (input.trim().strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(input.trim()),
+    }
+}
+
+fn select_next(state: &mut TableState, len: usize) {
+    if len == 0 {
+        state.select(None);
+        return;
+    }
+    state.select(match state.selected() {
+        Some(pos) if pos + 1 < len => Some(pos + 1),
+        Some(_) => Some(0),
+        None => Some(0),
+    })
+}
+
+fn select_previous(state: &mut TableState, len: usize) {
+    if len == 0 {
+        state.select(None);
+        return;
+    }
+    state.select(match state.selected() {
+        Some(0) => Some(len - 1),
+        Some(pos) => Some(pos - 1),
+        None => Some(0),
+    })
+}
+
+/// Group tracks into albums by artist and album name, each artist's albums oldest first.
+fn collect_albums<'a, I, F>(tracks: I, artist_of: F) -> Vec<Album<'a>>
+where
+    I: Iterator<Item = &'a Track>,
+    F: Fn(&'a Track) -> &'a str,
+{
+    let mut albums: BTreeMap<(&str, &str), Album> = BTreeMap::new();
+    for track in tracks {
+        let artist = artist_of(track);
+        albums
+            .entry((artist, track.album.as_str()))
+            .and_modify(|a| a.track_count += 1)
+            .or_insert(Album {
+                artist,
+                name: track.album.as_str(),
+                year: track.year(),
+                track_count: 1,
+            });
+    }
+    let mut albums: Vec<Album> = albums.into_values().collect();
+    albums.sort_by(|a, b| (a.artist, a.year, a.name).cmp(&(b.artist, b.year, b.name)));
+    albums
+}
+
+fn sort_by_position(tracks: &mut [&Track]) {
+    tracks.sort_by_key(|t| (t.disc_number, t.track_number));
+}
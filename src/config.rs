@@ -0,0 +1,261 @@
+use crate::keys::{Action, Bindings, KeyList};
+use crate::worker::Position;
+
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tui::style::Color;
+
+/// Settings from `config.toml`. Anything left out keeps its default, and
+/// command line options take precedence over the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub dlna_address: Option<String>,
+    pub naim_address: Option<String>,
+    pub library: Library,
+    /// How often the UI is redrawn, in milliseconds
+    pub tick_rate: Option<u64>,
+    /// Action names from `keys::ACTIONS`, each mapped to a key or a list of keys
+    pub keys: HashMap<String, KeyList>,
+    /// The default bindings with `keys` applied, built when the config is loaded
+    #[serde(skip)]
+    pub bindings: Bindings,
+    pub colors: Colors,
+    pub queue: Queue,
+    /// Action names mapped to whether to ask before running them
+    pub confirm: HashMap<String, bool>,
+    /// The actions which ask before they run, worked out from `confirm` when the config is loaded
+    #[serde(skip)]
+    pub confirmed: HashSet<Action>,
+}
+
+/// Actions which can't be undone or change a lot at once
+const CONFIRM_BY_DEFAULT: &[Action] = &[
+    Action::ClearQueue,
+    Action::ReplaceQueue,
+    Action::QueueArtist,
+    Action::Suspend,
+];
+
+/// Where the tracks are read from. The same as the `--csv`, `--db` and `--upnp` options.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Library {
+    pub csv: Option<String>,
+    pub db: Option<String>,
+    /// Only used when built with the `upnp` feature
+    pub upnp: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    /// The highlighted row in each pane, which is also shown in bold
+    #[serde(deserialize_with = "optional_color")]
+    pub selected: Option<Color>,
+    /// The playing entry in the playlist
+    #[serde(deserialize_with = "color")]
+    pub current: Color,
+    #[serde(deserialize_with = "color")]
+    pub message: Color,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            selected: None,
+            current: Color::Yellow,
+            message: Color::Red,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Queue {
+    /// Where `Enter`, `a` and `A` add tracks
+    pub add_to: Position,
+    /// Whether `Enter` moves on to the next track after adding one
+    pub advance: bool,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Queue {
+            add_to: Position::End,
+            advance: true,
+        }
+    }
+}
+
+impl Config {
+    /// `~/.config/mina/config.toml` on Linux, or the platform's equivalent.
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_default()
+            .join("mina")
+            .join("config.toml")
+    }
+
+    /// Read the config file, if there is one. A file given on the command line has to exist.
+    ///
+    /// Without a file every setting has its default, including the bindings and
+    /// the actions which ask first.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => (Config::default_path(), false),
+        };
+        let config_error = |e: &dyn Error| format!("Invalid config {}: {}", path.display(), e);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => String::new(),
+            Err(e) => return Err(config_error(&e).into()),
+        };
+        let mut config: Config = toml::from_str(&text).map_err(|e| config_error(&e))?;
+        config.bindings = Bindings::new(&config.keys).map_err(|e| config_error(&*e))?;
+        config.confirmed = config.confirmed_actions().map_err(|e| config_error(&*e))?;
+        Ok(config)
+    }
+
+    // `CONFIRM_BY_DEFAULT` with the `[confirm]` table applied
+    fn confirmed_actions(&self) -> Result<HashSet<Action>, Box<dyn Error>> {
+        let mut actions: HashSet<Action> = CONFIRM_BY_DEFAULT.iter().copied().collect();
+        for (name, &confirm) in &self.confirm {
+            let action = Action::from_name(name)
+                .ok_or_else(|| format!("unknown action '{}' in [confirm]", name))?;
+            if confirm {
+                actions.insert(action);
+            } else {
+                actions.remove(&action);
+            }
+        }
+        Ok(actions)
+    }
+}
+
+fn color<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    parse_color(&name).ok_or_else(|| serde::de::Error::custom(format!("unknown color '{}'", name)))
+}
+
+fn optional_color<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Color>, D::Error> {
+    color(deserializer).map(Some)
+}
+
+/// A color name such as "yellow" or "light-blue", a terminal color number or "#rrggbb".
+fn parse_color(name: &str) -> Option<Color> {
+    let name = name.trim().to_lowercase().replace(['-', '_', ' '], "");
+    if let Some(hex) = name.strip_prefix('#') {
+        let rgb = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)?;
+        return Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+    }
+    if let Ok(index) = name.parse() {
+        return Some(Color::Indexed(index));
+    }
+    Some(match name.as_str() {
+        "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> Result<Config, String> {
+        let path = std::env::temp_dir().join(format!("mina-{}.toml", name));
+        fs::write(&path, text).unwrap();
+        let config = Config::load(Some(&path)).map_err(|e| e.to_string());
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn colors_are_names_numbers_or_hex() {
+        assert_eq!(parse_color("yellow"), Some(Color::Yellow));
+        assert_eq!(parse_color(" Light-Blue "), Some(Color::LightBlue));
+        assert_eq!(parse_color("dark_grey"), Some(Color::DarkGray));
+        assert_eq!(parse_color("208"), Some(Color::Indexed(208)));
+        assert_eq!(parse_color("#FF8000"), Some(Color::Rgb(255, 128, 0)));
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("#gggggg"), None);
+        assert_eq!(parse_color("256"), None);
+        assert_eq!(parse_color("mauve"), None);
+    }
+
+    #[test]
+    fn settings_are_read() {
+        let config = load(
+            "settings",
+            "tick_rate = 100\n\
+            [colors]\n\
+            selected = \"cyan\"\n\
+            [queue]\n\
+            add_to = \"next\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.tick_rate, Some(100));
+        assert_eq!(config.colors.selected, Some(Color::Cyan));
+        assert_eq!(config.colors.current, Color::Yellow);
+        assert_eq!(config.queue.add_to, Position::Next);
+        assert!(config.queue.advance);
+    }
+
+    #[test]
+    fn confirm_changes_the_defaults() {
+        let config = load(
+            "confirm-defaults",
+            "[confirm]\nsuspend = false\nimport_playlist = true\n",
+        )
+        .unwrap();
+        assert!(config.confirmed.contains(&Action::ClearQueue));
+        assert!(config.confirmed.contains(&Action::ImportPlaylist));
+        assert!(!config.confirmed.contains(&Action::Suspend));
+    }
+
+    #[test]
+    fn a_missing_file_is_an_error_when_given() {
+        let path = std::env::temp_dir().join("mina-missing.toml");
+        let e = Config::load(Some(&path)).unwrap_err().to_string();
+        assert!(e.starts_with("Invalid config "), "{}", e);
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let e = load("syntax", "tick_rate = ").unwrap_err();
+        assert!(e.starts_with("Invalid config "), "{}", e);
+        let e = load("field", "tick-rate = 100").unwrap_err();
+        assert!(e.contains("unknown field `tick-rate`"), "{}", e);
+        let e = load("color", "[colors]\ncurrent = \"mauve\"").unwrap_err();
+        assert!(e.contains("unknown color 'mauve'"), "{}", e);
+        let e = load("keys", "[keys]\njump = \"J\"").unwrap_err();
+        assert!(e.contains("unknown action 'jump' in [keys]"), "{}", e);
+        let e = load("confirm", "[confirm]\njump = true").unwrap_err();
+        assert!(e.contains("unknown action 'jump' in [confirm]"), "{}", e);
+    }
+}
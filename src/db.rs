@@ -1,27 +1,157 @@
+use rusqlite::{types::ValueRef, Connection, OpenFlags, Row};
 use serde::Deserialize;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
 pub struct Track {
     pub id: u16,
     pub title: String,
     pub artist: String,
+    #[serde(default)]
+    pub album_artist: String,
     pub album: String,
+    #[serde(default)]
+    pub genre: String,
     pub album_art_id: u16,
     pub track_number: u16,
     pub disc_number: u8,
     pub track_id: String,
     pub duration: String,
+    pub date: String,
     pub mime_type: String,
 }
 
-pub fn read_tracks() -> Result<Vec<Track>, Box<dyn Error>> {
-    let mut rdr = csv::Reader::from_path("tracks.csv")?;
-    let mut tracks = Vec::with_capacity(10000);
+impl Track {
+    /// The album artist, falling back to the track artist when it isn't set.
+    pub fn album_artist_or_artist(&self) -> &str {
+        if self.album_artist.is_empty() {
+            &self.artist
+        } else {
+            &self.album_artist
+        }
+    }
+
+    /// The year part of the track's date, if it has one.
+    pub fn year(&self) -> Option<&str> {
+        self.date
+            .get(0..4)
+            .filter(|y| y.chars().all(|c| c.is_ascii_digit()))
+    }
+}
+
+#[cfg(test)]
+impl Track {
+    /// A FLAC track with the fields that matching and searching look at.
+    pub fn example(id: u16, title: &str, artist: &str, album: &str) -> Track {
+        Track {
+            id,
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album_artist: String::new(),
+            album: album.to_string(),
+            genre: String::new(),
+            album_art_id: 0,
+            track_number: 1,
+            disc_number: 1,
+            track_id: format!("64$0${}", id),
+            duration: "0:03:00.000".to_string(),
+            date: String::new(),
+            mime_type: "audio/x-flac".to_string(),
+        }
+    }
+}
+
+/// Somewhere the library's tracks can be loaded from.
+///
+/// The CSV dump and MiniDLNA's own database are supported. Other backends
+/// just need to produce the same `Track` values.
+pub trait TrackSource {
+    fn read_tracks(&self) -> Result<Vec<Track>, Box<dyn Error>>;
+}
+
+/// A CSV file created by running `dump_tracks.sql` against the MiniDLNA database.
+pub struct CsvSource {
+    path: PathBuf,
+}
+
+impl CsvSource {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        CsvSource { path: path.into() }
+    }
+
+    fn read(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        let mut rdr = csv::Reader::from_path(&self.path)?;
+        let mut tracks = Vec::with_capacity(10000);
+
+        for result in rdr.deserialize() {
+            let track: Track = result?;
+            tracks.push(track);
+        }
+        Ok(tracks)
+    }
+}
 
-    for result in rdr.deserialize() {
-        let track: Track = result?;
-        tracks.push(track);
+impl TrackSource for CsvSource {
+    fn read_tracks(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        self.read().map_err(|e| source_error(&self.path, e))
     }
-    Ok(tracks)
+}
+
+/// MiniDLNA's sqlite database, opened read-only and queried with `dump_tracks.sql`.
+pub struct SqliteSource {
+    path: PathBuf,
+}
+
+impl SqliteSource {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        SqliteSource { path: path.into() }
+    }
+
+    fn read(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        let conn = Connection::open_with_flags(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut stmt = conn.prepare(include_str!("../dump_tracks.sql"))?;
+        let tracks = stmt
+            .query_map(rusqlite::NO_PARAMS, track_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tracks)
+    }
+}
+
+impl TrackSource for SqliteSource {
+    fn read_tracks(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        self.read().map_err(|e| source_error(&self.path, e))
+    }
+}
+
+fn source_error(path: &Path, e: Box<dyn Error>) -> Box<dyn Error> {
+    format!("Failed to read tracks from {}: {}", path.display(), e).into()
+}
+
+fn track_from_row(row: &Row) -> rusqlite::Result<Track> {
+    Ok(Track {
+        id: row.get("id")?,
+        title: text(row, "title")?,
+        artist: text(row, "artist")?,
+        album_artist: text(row, "album_artist")?,
+        album: text(row, "album")?,
+        genre: text(row, "genre")?,
+        album_art_id: row.get::<_, Option<u16>>("album_art_id")?.unwrap_or(0),
+        track_number: row.get::<_, Option<u16>>("track_number")?.unwrap_or(0),
+        disc_number: row.get::<_, Option<u8>>("disc_number")?.unwrap_or(0),
+        track_id: text(row, "track_id")?,
+        duration: text(row, "duration")?,
+        date: text(row, "date")?,
+        mime_type: text(row, "mime_type")?,
+    })
+}
+
+// MiniDLNA's columns are loosely typed (a year-only date is stored as an integer, for example)
+fn text(row: &Row, column: &str) -> rusqlite::Result<String> {
+    Ok(match row.get_raw_checked(column)? {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) | ValueRef::Blob(t) => String::from_utf8_lossy(t).into_owned(),
+    })
 }
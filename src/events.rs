@@ -1,3 +1,5 @@
+use crate::worker::Reply;
+
 use std::io;
 use std::sync::mpsc;
 use std::thread;
@@ -9,65 +11,60 @@ use termion::input::TermRead;
 pub enum Event<I> {
     Input(I),
     Tick,
+    /// The outcome of a request to the streamer, sent from a background thread
+    Api(Reply),
 }
 
 /// A small event handler that wrap termion input and tick events. Each event
 /// type is handled in its own thread and returned to a common `Receiver`
 pub struct Events {
+    tx: mpsc::Sender<Event<Key>>,
     rx: mpsc::Receiver<Event<Key>>,
-    input_handle: thread::JoinHandle<()>,
-    tick_handle: thread::JoinHandle<()>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-    pub exit_key: Key,
     pub tick_rate: Duration,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
-            exit_key: Key::Char('q'),
             tick_rate: Duration::from_millis(250),
         }
     }
 }
 
 impl Events {
-    pub fn new() -> Events {
-        Events::with_config(Config::default())
-    }
-
     pub fn with_config(config: Config) -> Events {
         let (tx, rx) = mpsc::channel();
-        let input_handle = {
+        {
             let tx = tx.clone();
             thread::spawn(move || {
                 let stdin = io::stdin();
-                for evt in stdin.keys() {
-                    if let Ok(key) = evt {
-                        if let Err(err) = tx.send(Event::Input(key)) {
-                            eprintln!("{}", err);
-                            return;
-                        }
+                for key in stdin.keys().flatten() {
+                    // The receiver only goes away when the app is quitting
+                    if tx.send(Event::Input(key)).is_err() {
+                        return;
                     }
                 }
-            })
-        };
-        let tick_handle = {
+            });
+        }
+        {
+            let tx = tx.clone();
             thread::spawn(move || loop {
                 if tx.send(Event::Tick).is_err() {
                     break;
                 }
                 thread::sleep(config.tick_rate);
-            })
-        };
-        Events {
-            rx,
-            input_handle,
-            tick_handle,
+            });
         }
+        Events { tx, rx }
+    }
+
+    /// For other threads to add their own events
+    pub fn sender(&self) -> mpsc::Sender<Event<Key>> {
+        self.tx.clone()
     }
 
     pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
@@ -0,0 +1,536 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+use termion::event::Key;
+use Action::*;
+
+/// How long to wait for the next key of a sequence such as "gg" before giving up on it.
+const SEQUENCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Everything that can be bound to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    SwitchPane,
+    ToggleView,
+    Select,
+    QueueNext,
+    ReplaceQueue,
+    QueueAlbum,
+    QueueArtist,
+    RemoveEntry,
+    MoveEntryUp,
+    MoveEntryDown,
+    ClearQueue,
+    Undo,
+    Play,
+    PlayPause,
+    Stop,
+    NextTrack,
+    PreviousTrack,
+    VolumeUp,
+    VolumeDown,
+    PowerOn,
+    Suspend,
+    Search,
+    NextMatch,
+    PreviousMatch,
+    RevealMatch,
+    Close,
+    SavePlaylist,
+    OpenPlaylist,
+    ImportPlaylist,
+    ExportPlaylist,
+    Help,
+    Quit,
+}
+
+impl Action {
+    /// The action called `name` in the config file.
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .find(|info| info.name == name)
+            .map(|info| info.action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|info| info.action == self)
+            .map_or("", |info| info.name)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Up => "Move up",
+            Down => "Move down",
+            PageUp => "Move up a page",
+            PageDown => "Move down a page",
+            First => "Go to the top",
+            Last => "Go to the bottom",
+            SwitchPane => "Switch to the next pane",
+            ToggleView => "Browse by artist or by album",
+            Select => "Add the track, or play the playlist entry",
+            QueueNext => "Play the track next",
+            ReplaceQueue => "Replace the queue with the track",
+            QueueAlbum => "Add the album",
+            QueueArtist => "Add all of the artist's albums",
+            RemoveEntry => "Remove the playlist entry",
+            MoveEntryUp => "Move the playlist entry up",
+            MoveEntryDown => "Move the playlist entry down",
+            ClearQueue => "Clear the queue",
+            Undo => "Undo the last change to the queue",
+            Play => "Play",
+            PlayPause => "Pause or resume",
+            Stop => "Stop",
+            NextTrack => "Next track",
+            PreviousTrack => "Previous track",
+            VolumeUp => "Volume up",
+            VolumeDown => "Volume down",
+            PowerOn => "Wake the streamer",
+            Suspend => "Put the streamer in standby",
+            Search => "Search the library",
+            NextMatch => "Next match",
+            PreviousMatch => "Previous match",
+            RevealMatch => "Go to the match's artist",
+            Close => "Close the search results",
+            SavePlaylist => "Save the queue",
+            OpenPlaylist => "Add a saved playlist",
+            ImportPlaylist => "Add an M3U or PLS file",
+            ExportPlaylist => "Export the queue as M3U8",
+            Help => "Show the keys",
+            Quit => "Quit",
+        }
+    }
+
+    pub fn category(self) -> Category {
+        match self {
+            Up | Down | PageUp | PageDown | First | Last | SwitchPane | ToggleView => {
+                Category::Browsing
+            }
+            Select | QueueNext | ReplaceQueue | QueueAlbum | QueueArtist | RemoveEntry
+            | MoveEntryUp | MoveEntryDown | ClearQueue | Undo => Category::Queue,
+            Play | PlayPause | Stop | NextTrack | PreviousTrack | VolumeUp | VolumeDown
+            | PowerOn | Suspend => Category::Playback,
+            Search | NextMatch | PreviousMatch | RevealMatch | Close => Category::Searching,
+            SavePlaylist | OpenPlaylist | ImportPlaylist | ExportPlaylist => {
+                Category::SavedPlaylists
+            }
+            Help | Quit => Category::General,
+        }
+    }
+}
+
+/// How actions are grouped in the help, in the order they're listed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Browsing,
+    Queue,
+    Playback,
+    Searching,
+    SavedPlaylists,
+    General,
+}
+
+impl Category {
+    pub fn title(self) -> &'static str {
+        match self {
+            Category::Browsing => "Browsing",
+            Category::Queue => "Queue",
+            Category::Playback => "Playback",
+            Category::Searching => "Searching",
+            Category::SavedPlaylists => "Saved playlists",
+            Category::General => "General",
+        }
+    }
+}
+
+pub struct ActionInfo {
+    pub action: Action,
+    /// What the action is called in the config file's `[keys]` table
+    pub name: &'static str,
+    default_keys: &'static [&'static str],
+}
+
+const fn info(
+    action: Action,
+    name: &'static str,
+    default_keys: &'static [&'static str],
+) -> ActionInfo {
+    ActionInfo {
+        action,
+        name,
+        default_keys,
+    }
+}
+
+/// Every action with its default keys, in the order they're listed in the help.
+pub const ACTIONS: &[ActionInfo] = &[
+    info(Up, "up", &["<Up>", "k"]),
+    info(Down, "down", &["<Down>", "j"]),
+    info(PageUp, "page_up", &["<PageUp>", "<C-b>"]),
+    info(PageDown, "page_down", &["<PageDown>", "<C-f>"]),
+    info(First, "first", &["gg", "<Home>"]),
+    info(Last, "last", &["G", "<End>"]),
+    info(SwitchPane, "switch_pane", &["<Tab>"]),
+    info(ToggleView, "toggle_view", &["v"]),
+    info(Select, "select", &["<Enter>"]),
+    info(QueueNext, "queue_next", &["i"]),
+    info(ReplaceQueue, "replace_queue", &["R"]),
+    info(QueueAlbum, "queue_album", &["a"]),
+    info(QueueArtist, "queue_artist", &["A"]),
+    info(RemoveEntry, "remove_entry", &["d"]),
+    info(MoveEntryUp, "move_entry_up", &["K"]),
+    info(MoveEntryDown, "move_entry_down", &["J"]),
+    info(ClearQueue, "clear_queue", &["C"]),
+    info(Undo, "undo", &["u"]),
+    info(Play, "play", &["p"]),
+    info(PlayPause, "play_pause", &["<Space>"]),
+    info(Stop, "stop", &["s"]),
+    info(NextTrack, "next_track", &[">"]),
+    info(PreviousTrack, "previous_track", &["<"]),
+    info(VolumeUp, "volume_up", &["+"]),
+    info(VolumeDown, "volume_down", &["-"]),
+    info(PowerOn, "power_on", &["P"]),
+    info(Suspend, "suspend", &["S"]),
+    info(Search, "search", &["/"]),
+    info(NextMatch, "next_match", &["n"]),
+    info(PreviousMatch, "previous_match", &["N"]),
+    info(RevealMatch, "reveal_match", &["r"]),
+    info(Close, "close", &["<Esc>"]),
+    info(SavePlaylist, "save_playlist", &["w"]),
+    info(OpenPlaylist, "open_playlist", &["o"]),
+    info(ImportPlaylist, "import_playlist", &["I"]),
+    info(ExportPlaylist, "export_playlist", &["E"]),
+    info(Help, "help", &["?"]),
+    info(Quit, "quit", &["q"]),
+];
+
+/// The keys for an action in the config file: either one, or a list.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        let keys = match self {
+            KeyList::One(key) => std::slice::from_ref(key),
+            KeyList::Many(keys) => keys.as_slice(),
+        };
+        keys.iter().map(String::as_str)
+    }
+}
+
+/// Which keys, or sequences of keys, run which actions.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    map: HashMap<Vec<Key>, Action>,
+    /// Keys pressed so far which are the start of a longer binding
+    pending: Vec<Key>,
+    /// When the last of the pending keys was pressed
+    pressed_at: Instant,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings::new(&HashMap::new()).expect("the default bindings are valid")
+    }
+}
+
+impl Bindings {
+    /// The default bindings, with the actions in `config` bound to its keys instead.
+    pub fn new(config: &HashMap<String, KeyList>) -> Result<Bindings, Box<dyn Error>> {
+        let mut configured: Vec<(Vec<Key>, Action)> = Vec::new();
+        for (name, keys) in config {
+            let action = Action::from_name(name)
+                .ok_or_else(|| format!("unknown action '{}' in [keys]", name))?;
+            for spec in keys.iter() {
+                let keys = parse_keys(spec)?;
+                if let Some((_, other)) = configured.iter().find(|(k, _)| overlaps(k, &keys)) {
+                    return Err(format!(
+                        "'{}' for {} clashes with the keys for {}",
+                        spec,
+                        name,
+                        other.name()
+                    )
+                    .into());
+                }
+                configured.push((keys, action));
+            }
+        }
+
+        let mut map = HashMap::new();
+        for info in ACTIONS
+            .iter()
+            .filter(|info| !config.contains_key(info.name))
+        {
+            for spec in info.default_keys {
+                let keys = parse_keys(spec)?;
+                // Keys given in the config take over from the defaults
+                if !configured.iter().any(|(k, _)| overlaps(k, &keys)) {
+                    map.insert(keys, info.action);
+                }
+            }
+        }
+        map.extend(configured);
+        Ok(Bindings {
+            map,
+            pending: Vec::new(),
+            pressed_at: Instant::now(),
+        })
+    }
+
+    /// Add a key press to the ones before it, returning the action once they make up a binding.
+    ///
+    /// A sequence is abandoned if its next key doesn't come within
+    /// `SEQUENCE_TIMEOUT`, or when `Esc` is pressed part way through it.
+    pub fn feed(&mut self, key: Key) -> Option<Action> {
+        if self.pressed_at.elapsed() > SEQUENCE_TIMEOUT {
+            self.pending.clear();
+        }
+        self.pressed_at = Instant::now();
+        if key == Key::Esc && !self.pending.is_empty() {
+            self.pending.clear();
+            return None;
+        }
+        self.pending.push(key);
+        if let Some(&action) = self.map.get(&self.pending) {
+            self.pending.clear();
+            return Some(action);
+        }
+        if self.map.keys().any(|keys| keys.starts_with(&self.pending)) {
+            return None;
+        }
+        // Not the start of any binding, so start again from this key
+        let retry = self.pending.len() > 1;
+        self.pending.clear();
+        if retry {
+            self.feed(key)
+        } else {
+            None
+        }
+    }
+
+    /// The start of a sequence typed so far, for showing while the rest is waited for.
+    pub fn pending(&self) -> Option<String> {
+        if self.pending.is_empty() || self.pressed_at.elapsed() > SEQUENCE_TIMEOUT {
+            None
+        } else {
+            Some(format_keys(&self.pending))
+        }
+    }
+
+    /// The keys bound to an action, for showing to the user.
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .map
+            .iter()
+            .filter(|(_, a)| **a == action)
+            .map(|(keys, _)| format_keys(keys))
+            .collect();
+        keys.sort_by_key(|k| (k.chars().count() == 1, k.clone()));
+        keys
+    }
+}
+
+// One sequence can't be the start of another, since there'd be no telling
+// whether to run the shorter one or wait for the rest of the longer one.
+fn overlaps(a: &[Key], b: &[Key]) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+/// Parse keys written like "j", "G", "gg", "<C-d>", "<Up>" or "Space".
+pub fn parse_keys(spec: &str) -> Result<Vec<Key>, Box<dyn Error>> {
+    if let Some(key) = named_key(spec) {
+        return Ok(vec![key]);
+    }
+    let mut keys = Vec::new();
+    let mut rest = spec;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>').filter(|&end| end > 1) {
+                let name = &rest[1..end];
+                keys.push(named_key(name).ok_or_else(|| format!("unknown key '<{}>'", name))?);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        keys.push(Key::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    if keys.is_empty() {
+        return Err("empty key in [keys]".into());
+    }
+    Ok(keys)
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    let lower = name.to_lowercase();
+    let modified = |prefixes: &[&str]| {
+        let rest = prefixes.iter().find_map(|p| lower.strip_prefix(p))?;
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = modified(&["c-", "ctrl-"]) {
+        return Some(Key::Ctrl(c));
+    }
+    if let Some(c) = modified(&["a-", "m-", "alt-"]) {
+        return Some(Key::Alt(c));
+    }
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+        return Some(Key::F(n));
+    }
+    Some(match lower.as_str() {
+        "enter" | "return" | "cr" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        "esc" | "escape" => Key::Esc,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "pageup" | "pgup" => Key::PageUp,
+        "pagedown" | "pgdn" => Key::PageDown,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "backspace" | "bs" => Key::Backspace,
+        "delete" | "del" => Key::Delete,
+        "insert" => Key::Insert,
+        "backtab" | "s-tab" => Key::BackTab,
+        _ => return None,
+    })
+}
+
+fn format_keys(keys: &[Key]) -> String {
+    let names: Vec<String> = keys.iter().map(|&key| format_key(key)).collect();
+    if keys
+        .iter()
+        .all(|key| matches!(key, Key::Char(c) if !c.is_whitespace()))
+    {
+        names.concat()
+    } else {
+        names.join(" ")
+    }
+}
+
+fn format_key(key: Key) -> String {
+    match key {
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::Char(' ') => "Space".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c),
+        Key::Alt(c) => format!("Alt-{}", c),
+        Key::F(n) => format!("F{}", n),
+        Key::PageUp => "PageUp".to_string(),
+        Key::PageDown => "PageDown".to_string(),
+        Key::BackTab => "Shift-Tab".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(config: &[(&str, &str)]) -> Result<Bindings, String> {
+        let config = config
+            .iter()
+            .map(|&(name, keys)| (name.to_string(), KeyList::One(keys.to_string())))
+            .collect();
+        Bindings::new(&config).map_err(|e| e.to_string())
+    }
+
+    fn feed(bindings: &mut Bindings, keys: &str) -> Vec<Option<Action>> {
+        parse_keys(keys)
+            .unwrap()
+            .into_iter()
+            .map(|key| bindings.feed(key))
+            .collect()
+    }
+
+    #[test]
+    fn keys_are_characters_or_names() {
+        assert_eq!(
+            parse_keys("gg").unwrap(),
+            vec![Key::Char('g'), Key::Char('g')]
+        );
+        assert_eq!(
+            parse_keys("<C-d><PageDown>x").unwrap(),
+            vec![Key::Ctrl('d'), Key::PageDown, Key::Char('x')]
+        );
+        assert_eq!(parse_keys("Enter").unwrap(), vec![Key::Char('\n')]);
+        assert_eq!(parse_keys("<lt>").unwrap(), vec![Key::Char('<')]);
+        assert_eq!(
+            parse_keys("<>").unwrap(),
+            vec![Key::Char('<'), Key::Char('>')]
+        );
+        assert_eq!(parse_keys("<F5>").unwrap(), vec![Key::F(5)]);
+        assert!(parse_keys("<Nope>").is_err());
+        assert!(parse_keys("").is_err());
+    }
+
+    #[test]
+    fn sequences_wait_for_their_last_key() {
+        let mut bindings = Bindings::default();
+        assert_eq!(feed(&mut bindings, "gg"), vec![None, Some(First)]);
+        assert_eq!(bindings.pending(), None);
+        assert_eq!(feed(&mut bindings, "G"), vec![Some(Last)]);
+        // A key which doesn't continue the sequence starts again
+        assert_eq!(feed(&mut bindings, "gj"), vec![None, Some(Down)]);
+        assert_eq!(feed(&mut bindings, "g"), vec![None]);
+        assert_eq!(bindings.pending(), Some("g".to_string()));
+    }
+
+    #[test]
+    fn sequences_are_abandoned() {
+        let mut bindings = Bindings::default();
+        assert_eq!(feed(&mut bindings, "g<Esc>g"), vec![None, None, None]);
+        // Esc on its own still closes
+        assert_eq!(feed(&mut bindings, "<Esc><Esc>"), vec![None, Some(Close)]);
+
+        feed(&mut bindings, "g");
+        bindings.pressed_at -= SEQUENCE_TIMEOUT * 2;
+        assert_eq!(bindings.pending(), None);
+        assert_eq!(feed(&mut bindings, "g"), vec![None]);
+    }
+
+    #[test]
+    fn configured_keys_replace_the_defaults() {
+        let mut bindings = bindings(&[("down", "<C-n>"), ("quit", "ZZ")]).unwrap();
+        assert_eq!(bindings.keys_for(Down), vec!["Ctrl-n"]);
+        assert_eq!(feed(&mut bindings, "j"), vec![None]);
+        assert_eq!(feed(&mut bindings, "ZZ"), vec![None, Some(Quit)]);
+        assert_eq!(bindings.keys_for(Quit), vec!["ZZ"]);
+    }
+
+    #[test]
+    fn defaults_which_overlap_configured_keys_are_dropped() {
+        // "g" would make "gg" for first unreachable
+        let mut bindings = bindings(&[("play", "g")]).unwrap();
+        assert_eq!(feed(&mut bindings, "g"), vec![Some(Play)]);
+        assert_eq!(bindings.keys_for(First), vec!["Home"]);
+    }
+
+    #[test]
+    fn configured_keys_must_not_overlap() {
+        let e = bindings(&[("play", "x"), ("stop", "xy")]).unwrap_err();
+        assert!(e.contains("clashes with the keys for"), "{}", e);
+        let e = bindings(&[("jump", "J")]).unwrap_err();
+        assert_eq!(e, "unknown action 'jump' in [keys]");
+    }
+}
@@ -0,0 +1,325 @@
+use crate::api::PlaylistEntry;
+use crate::db::Track;
+use crate::search;
+
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A line from a playlist file, along with the title the file gives it, if any.
+struct Item {
+    location: String,
+    title: Option<String>,
+}
+
+/// A library track with its names normalized for matching against playlist entries.
+struct Names<'a> {
+    track: &'a Track,
+    title: String,
+    artist: String,
+    album_artist: String,
+    album: String,
+}
+
+impl<'a> Names<'a> {
+    fn new(track: &'a Track) -> Names<'a> {
+        Names {
+            track,
+            title: normalize(&track.title),
+            artist: normalize(&track.artist),
+            album_artist: normalize(&track.album_artist),
+            album: normalize(&track.album),
+        }
+    }
+}
+
+/// The library tracks found for a playlist file's entries, in playlist order.
+pub struct Import<'a> {
+    pub tracks: Vec<&'a Track>,
+    /// The locations of the entries which couldn't be found
+    pub unmatched: Vec<String>,
+}
+
+/// Read an M3U, M3U8 or PLS file and find its entries in the library.
+///
+/// Entries which point at the DLNA server are matched by their id. Anything
+/// else, such as a path from another player, is matched by title along with
+/// the artist or album taken from the `#EXTINF` line or the path.
+pub fn import<'a>(path: &Path, tracks: &'a [Track]) -> Result<Import<'a>, Box<dyn Error>> {
+    let contents =
+        fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    // M3U files are often in the system's legacy encoding rather than UTF-8
+    let contents = String::from_utf8_lossy(&contents);
+    let contents = contents.trim_start_matches('\u{feff}');
+    let is_pls = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pls"))
+        || contents.trim_start().starts_with("[playlist]");
+    let items = if is_pls {
+        parse_pls(contents)
+    } else {
+        parse_m3u(contents)
+    };
+
+    let library: Vec<Names> = tracks.iter().map(Names::new).collect();
+    let mut import = Import {
+        tracks: Vec::new(),
+        unmatched: Vec::new(),
+    };
+    for item in items {
+        match find_track(&item, &library) {
+            Some(track) => import.tracks.push(track),
+            None => import.unmatched.push(item.location),
+        }
+    }
+    Ok(import)
+}
+
+/// Write the queue as an extended M3U file, with the tracks' DLNA server URIs.
+pub fn export(path: &Path, entries: &[PlaylistEntry]) -> Result<(), Box<dyn Error>> {
+    let mut m3u = String::from("#EXTM3U\n");
+    for entry in entries {
+        m3u.push_str(&format!(
+            "#EXTINF:-1,{} - {}\n{}\n",
+            entry.artistName, entry.name, entry.uri
+        ));
+    }
+    fs::write(path, m3u).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+fn parse_m3u(contents: &str) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut title = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // "#EXTINF:<seconds>,<artist> - <title>"
+            title = info.split_once(',').map(|(_, t)| t.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            items.push(Item {
+                location: line.to_string(),
+                title: title.take(),
+            });
+        }
+    }
+    items
+}
+
+// "File1=...", "Title1=..." and so on, which aren't necessarily in order
+fn parse_pls(contents: &str) -> Vec<Item> {
+    let mut files = BTreeMap::new();
+    let mut titles = BTreeMap::new();
+    for line in contents.lines().map(str::trim) {
+        let (key, value) = match line.split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let key = key.to_ascii_lowercase();
+        if let Some(n) = key.strip_prefix("file").and_then(|n| n.parse::<u32>().ok()) {
+            files.insert(n, value.trim().to_string());
+        } else if let Some(n) = key
+            .strip_prefix("title")
+            .and_then(|n| n.parse::<u32>().ok())
+        {
+            titles.insert(n, value.trim().to_string());
+        }
+    }
+    files
+        .into_iter()
+        .map(|(n, location)| Item {
+            location,
+            title: titles.remove(&n),
+        })
+        .collect()
+}
+
+fn find_track<'a>(item: &Item, library: &[Names<'a>]) -> Option<&'a Track> {
+    if let Some(id) = media_item_id(&item.location) {
+        return library.iter().map(|n| n.track).find(|t| t.id == id);
+    }
+
+    let mut titles = Vec::new();
+    let mut artists = Vec::new();
+    let mut albums = Vec::new();
+    if let Some(title) = &item.title {
+        match title.split_once(" - ") {
+            Some((artist, title)) => {
+                artists.push(normalize(artist));
+                titles.push(normalize(title));
+            }
+            None => titles.push(normalize(title)),
+        }
+    }
+    // Players usually lay files out as ".../Artist/Album/01 Title.flac"
+    let path = file_path(&item.location);
+    let mut parents = path.ancestors().skip(1).filter_map(|p| p.file_name());
+    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+        titles.push(normalize(stem));
+        // without a track number like "01 ", "03 - " or "1-01. "
+        let title = stem
+            .trim_start_matches(|c: char| c.is_ascii_digit() || c == '-')
+            .trim_start_matches([' ', '-', '.', '_']);
+        titles.push(normalize(title));
+    }
+    if let Some(album) = parents.next() {
+        albums.push(normalize(&album.to_string_lossy()));
+    }
+    if let Some(artist) = parents.next() {
+        artists.push(normalize(&artist.to_string_lossy()));
+    }
+
+    let candidates: Vec<&Names> = library
+        .iter()
+        .filter(|n| titles.contains(&n.title))
+        .collect();
+    let score = |n: &Names| {
+        let artist = artists.contains(&n.artist) || artists.contains(&n.album_artist);
+        let album = albums.contains(&n.album);
+        artist as u8 * 2 + album as u8
+    };
+    let best = candidates.iter().copied().max_by_key(|n| score(n))?;
+    // Only trust a bare title match if it's the only track with that title
+    if score(best) > 0 || candidates.len() == 1 {
+        Some(best.track)
+    } else {
+        None
+    }
+}
+
+// MiniDLNA URIs look like "http://host:8200/MediaItems/3590.flac"
+fn media_item_id(location: &str) -> Option<u16> {
+    let (_, name) = location.rsplit_once("/MediaItems/")?;
+    name.split('.').next()?.parse().ok()
+}
+
+fn file_path(location: &str) -> PathBuf {
+    let location = location.replace('\\', "/");
+    match Url::parse(&location) {
+        Ok(url) if url.scheme() == "file" => url.to_file_path().unwrap_or_default(),
+        // Including Windows paths, whose drive letter looks like a URL scheme
+        _ => PathBuf::from(location),
+    }
+}
+
+// Accent-insensitive, as searching is, since file names often leave the accents out
+fn normalize(s: &str) -> String {
+    search::normalize(s.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> Vec<Track> {
+        vec![
+            Track::example(1, "Hunter", "Björk", "Homogenic"),
+            Track::example(2, "Jóga", "Björk", "Homogenic"),
+            Track::example(3, "Intro", "Massive Attack", "Mezzanine"),
+            Track::example(4, "Intro", "The xx", "xx"),
+            Track::example(5, "99 Problems", "Jay-Z", "The Black Album"),
+        ]
+    }
+
+    fn find(location: &str, title: Option<&str>, tracks: &[Track]) -> Option<u16> {
+        let item = Item {
+            location: location.to_string(),
+            title: title.map(str::to_string),
+        };
+        let library: Vec<Names> = tracks.iter().map(Names::new).collect();
+        find_track(&item, &library).map(|t| t.id)
+    }
+
+    #[test]
+    fn m3u_entries_keep_their_extinf_titles() {
+        let items = parse_m3u(
+            "#EXTM3U\n\
+            #EXTINF:255,Björk - Hunter\n\
+            Björk/Homogenic/01 Hunter.flac\n\
+            \n\
+            # a comment\n\
+            ../other/track.mp3\r\n",
+        );
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].location, "Björk/Homogenic/01 Hunter.flac");
+        assert_eq!(items[0].title.as_deref(), Some("Björk - Hunter"));
+        assert_eq!(items[1].location, "../other/track.mp3");
+        assert_eq!(items[1].title, None);
+    }
+
+    #[test]
+    fn pls_entries_are_in_number_order() {
+        let items = parse_pls(
+            "[playlist]\n\
+            File2=http://radio/stream\n\
+            Title1=Björk - Hunter\n\
+            file1=C:\\Music\\Björk\\Homogenic\\01 Hunter.flac\n\
+            Length1=255\n\
+            NumberOfEntries=2\n\
+            Version=2\n",
+        );
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0].location,
+            "C:\\Music\\Björk\\Homogenic\\01 Hunter.flac"
+        );
+        assert_eq!(items[0].title.as_deref(), Some("Björk - Hunter"));
+        assert_eq!(items[1].location, "http://radio/stream");
+        assert_eq!(items[1].title, None);
+    }
+
+    #[test]
+    fn server_uris_match_by_id() {
+        let tracks = library();
+        let uri = "http://192.168.0.123:8200/MediaItems/2.flac";
+        assert_eq!(find(uri, Some("Wrong - Title"), &tracks), Some(2));
+        assert_eq!(find("http://host/MediaItems/99.flac", None, &tracks), None);
+    }
+
+    #[test]
+    fn paths_match_by_title_artist_and_album() {
+        let tracks = library();
+        assert_eq!(
+            find("/music/Björk/Homogenic/01 Hunter.flac", None, &tracks),
+            Some(1)
+        );
+        assert_eq!(
+            find("Massive Attack/Mezzanine/01 - Intro.mp3", None, &tracks),
+            Some(3)
+        );
+        assert_eq!(find("../xx/1-01. Intro.flac", None, &tracks), Some(4));
+        assert_eq!(
+            find("C:\\Music\\Jay-Z\\99 Problems.mp3", None, &tracks),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn file_urls_are_decoded() {
+        let tracks = library();
+        let url = "file:///music/Bj%C3%B6rk/Homogenic/02%20J%C3%B3ga.flac";
+        assert_eq!(find(url, None, &tracks), Some(2));
+    }
+
+    #[test]
+    fn accents_are_ignored() {
+        let tracks = library();
+        assert_eq!(find("Bjork/Homogenic/02 Joga.flac", None, &tracks), Some(2));
+        assert_eq!(find("x.mp3", Some("Bjork - Joga"), &tracks), Some(2));
+    }
+
+    #[test]
+    fn extinf_gives_the_artist() {
+        let tracks = library();
+        assert_eq!(find("intro.mp3", Some("The xx - Intro"), &tracks), Some(4));
+    }
+
+    #[test]
+    fn ambiguous_titles_are_not_guessed() {
+        let tracks = library();
+        assert_eq!(find("Intro.mp3", None, &tracks), None);
+        assert_eq!(find("Hunter.mp3", None, &tracks), Some(1));
+        assert_eq!(find("Unknown Song.mp3", None, &tracks), None);
+    }
+}
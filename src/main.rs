@@ -1,22 +1,81 @@
+use crate::config::Config;
+use crate::db::TrackSource;
 use crate::events::{Event, Events};
+use crate::ssdp::{Device, DeviceKind};
+use crate::widgets::StatefulList;
 use clap::Clap;
-use std::{error::Error, io};
+use std::{error::Error, io, path::PathBuf, time::Duration};
 use termion::{event::Key, raw::IntoRawMode, screen::AlternateScreen};
-use tui::backend::TermionBackend;
+use tui::backend::{Backend, TermionBackend};
 use tui::Terminal;
 
 mod api;
 mod app;
+mod config;
 mod db;
 mod events;
+mod keys;
+mod m3u;
+mod playlists;
+mod search;
+mod ssdp;
 mod ui;
+#[cfg(feature = "upnp")]
+mod upnp;
 mod widgets;
+mod worker;
 
 #[derive(Clap)]
 #[clap(version = "0.1")]
 struct Opts {
-    dlna_address: String,
-    naim_address: String,
+    /// The DLNA server, found on the local network if not given
+    dlna_address: Option<String>,
+    /// The streamer, found on the local network if not given
+    naim_address: Option<String>,
+    /// CSV file of tracks dumped from the MiniDLNA database [default: tracks.csv]
+    #[clap(long)]
+    csv: Option<String>,
+    /// Read tracks from MiniDLNA's sqlite database instead of a CSV file
+    #[clap(long)]
+    db: Option<String>,
+    /// Browse the DLNA server's ContentDirectory service for tracks
+    #[cfg(feature = "upnp")]
+    #[clap(long)]
+    upnp: bool,
+    /// Add the tracks from an M3U, M3U8 or PLS file to the queue, then exit
+    #[clap(long)]
+    import: Option<PathBuf>,
+    /// Write the queue to an M3U8 file, then exit
+    #[clap(long)]
+    export: Option<PathBuf>,
+    /// Read settings from this file instead of ~/.config/mina/config.toml
+    #[clap(long)]
+    config: Option<PathBuf>,
+}
+
+impl Opts {
+    /// Fill in whatever wasn't given on the command line from the config file.
+    fn apply_config(&mut self, config: &Config) {
+        if self.dlna_address.is_none() {
+            self.dlna_address = config.dlna_address.clone();
+        }
+        if self.naim_address.is_none() {
+            self.naim_address = config.naim_address.clone();
+        }
+        // Choosing a library on the command line replaces the config's choice as a whole
+        #[cfg(feature = "upnp")]
+        let upnp = self.upnp;
+        #[cfg(not(feature = "upnp"))]
+        let upnp = false;
+        if self.csv.is_none() && self.db.is_none() && !upnp {
+            self.csv = config.library.csv.clone();
+            self.db = config.library.db.clone();
+            #[cfg(feature = "upnp")]
+            {
+                self.upnp = config.library.upnp;
+            }
+        }
+    }
 }
 
 fn check_http_prefix(addr: String) -> String {
@@ -27,50 +86,162 @@ fn check_http_prefix(addr: String) -> String {
     }
 }
 
+#[cfg_attr(not(feature = "upnp"), allow(unused_variables))]
+fn track_source(opts: &Opts, dlna_address: &str) -> Box<dyn TrackSource> {
+    #[cfg(feature = "upnp")]
+    {
+        if opts.upnp {
+            return Box::new(upnp::UpnpSource::new(dlna_address));
+        }
+    }
+    match &opts.db {
+        Some(path) => Box::new(db::SqliteSource::new(path)),
+        None => Box::new(db::CsvSource::new(
+            opts.csv.as_deref().unwrap_or("tracks.csv"),
+        )),
+    }
+}
+
+fn device_name(kind: DeviceKind) -> &'static str {
+    match kind {
+        DeviceKind::MediaServer => "DLNA server",
+        DeviceKind::Streamer => "streamer",
+    }
+}
+
+/// The device's address from the command line, or from discovery as long as only one was found.
+fn device_address(
+    given: &Option<String>,
+    devices: &[Device],
+    kind: DeviceKind,
+) -> Result<String, Box<dyn Error>> {
+    if let Some(addr) = given {
+        return Ok(check_http_prefix(addr.clone()));
+    }
+    let mut found = devices.iter().filter(|d| d.kind == kind);
+    match (found.next(), found.next()) {
+        (Some(device), None) => Ok(device.address.clone()),
+        (Some(_), Some(_)) => Err(format!(
+            "More than one {} found. Give its address on the command line.",
+            device_name(kind)
+        )
+        .into()),
+        (None, _) => Err(format!(
+            "No {} found. Give its address on the command line.",
+            device_name(kind)
+        )
+        .into()),
+    }
+}
+
+/// Handle `--import` and `--export` without starting the UI.
+fn run_batch(opts: &Opts, devices: &[Device]) -> Result<(), Box<dyn Error>> {
+    let dest_addr = device_address(&opts.naim_address, devices, DeviceKind::Streamer)?;
+    if let Some(path) = &opts.import {
+        let src_addr = device_address(&opts.dlna_address, devices, DeviceKind::MediaServer)?;
+        let tracks = track_source(opts, &src_addr).read_tracks()?;
+        let import = m3u::import(path, &tracks)?;
+        for location in &import.unmatched {
+            eprintln!("Not found: {}", location);
+        }
+        api::Api::new(&dest_addr, &src_addr).queue_tracks(&import.tracks)?;
+        println!("Added {} tracks to the queue", import.tracks.len());
+    }
+    if let Some(path) = &opts.export {
+        // Only the streamer is needed to read the queue
+        let playlist = api::Api::new(&dest_addr, "").get_playlist()?;
+        let entries = playlist.children.unwrap_or_default();
+        m3u::export(path, &entries)?;
+        println!("Wrote {} entries to {}", entries.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Pick the discovered device of the given kind, asking the user if there's more than one.
+fn choose_device<B: Backend>(
+    terminal: &mut Terminal<B>,
+    events: &Events,
+    devices: &[Device],
+    kind: DeviceKind,
+) -> Result<String, Box<dyn Error>> {
+    let found: Vec<Device> = devices.iter().filter(|d| d.kind == kind).cloned().collect();
+    if found.len() < 2 {
+        return device_address(&None, devices, kind);
+    }
+    let title = format!("Choose a {}", device_name(kind));
+
+    let mut list = StatefulList::with_items(found);
+    loop {
+        terminal.draw(|f| ui::draw_device_picker(f, &title, &mut list))?;
+
+        if let Event::Input(key) = events.next()? {
+            match key {
+                Key::Up => list.previous(1),
+                Key::Down => list.next(1),
+                Key::Char('\n') => {
+                    let i = list.state.selected().unwrap_or(0);
+                    return Ok(list.items[i].address.clone());
+                }
+                Key::Char('q') | Key::Esc => return Err("No device chosen".into()),
+                _ => {}
+            }
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let opts = Opts::parse();
+    let mut opts = Opts::parse();
+    let config = Config::load(opts.config.as_deref())?;
+    opts.apply_config(&config);
+    let devices = if opts.dlna_address.is_none() || opts.naim_address.is_none() {
+        ssdp::discover(Duration::from_secs(2))?
+    } else {
+        Vec::new()
+    };
+    if opts.import.is_some() || opts.export.is_some() {
+        return run_batch(&opts, &devices);
+    }
     // Terminal initialization
     let stdout = io::stdout().into_raw_mode()?;
     //let stdout = MouseTerminal::from(stdout);
     let stdout = AlternateScreen::from(stdout);
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
-    let src_addr = check_http_prefix(opts.dlna_address);
-    let dest_addr = check_http_prefix(opts.naim_address);
+    let mut events_config = events::Config::default();
+    if let Some(tick_rate) = config.tick_rate {
+        events_config.tick_rate = Duration::from_millis(tick_rate);
+    }
+    let events = Events::with_config(events_config);
+
+    let src_addr = match &opts.dlna_address {
+        Some(addr) => check_http_prefix(addr.clone()),
+        None => choose_device(&mut terminal, &events, &devices, DeviceKind::MediaServer)?,
+    };
+    let dest_addr = match &opts.naim_address {
+        Some(addr) => check_http_prefix(addr.clone()),
+        None => choose_device(&mut terminal, &events, &devices, DeviceKind::Streamer)?,
+    };
 
-    let events = Events::new();
-    let tracks = db::read_tracks()?;
+    let tracks = track_source(&opts, &src_addr).read_tracks()?;
     let naim_api = api::Api::new(dest_addr.as_str(), src_addr.as_str());
-    let mut app = app::App::new(naim_api, &tracks);
+    worker::poll_status(naim_api.clone(), Duration::from_secs(1), events.sender());
+    let commands = worker::spawn(naim_api, events.sender());
+    let store = playlists::PlaylistStore::new(playlists::PlaylistStore::default_dir());
+    let mut app = app::App::new(commands, store, &config, &tracks);
 
     loop {
         terminal.draw(|f| ui::draw(f, &mut app))?;
 
         match events.next()? {
-            Event::Input(key) => match key {
-                Key::Char(c) => {
-                    app.on_key(c);
-                }
-                Key::Up => {
-                    app.on_up();
-                }
-                Key::Down => {
-                    app.on_down();
-                }
-                Key::PageDown => {
-                    app.on_page_down();
-                }
-                Key::PageUp => {
-                    app.on_page_up();
-                }
-                Key::Backspace => {
-                    app.on_backspace();
-                }
-                _ => {}
-            },
+            Event::Input(key) => {
+                app.on_key(key);
+            }
             Event::Tick => {
                 app.on_tick();
             }
+            Event::Api(reply) => {
+                app.on_api_reply(reply);
+            }
         }
         if app.should_quit {
             break;
@@ -0,0 +1,66 @@
+use crate::api::PlaylistEntry;
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Copies of the streamer's queue, saved by name as JSON files.
+pub struct PlaylistStore {
+    dir: PathBuf,
+}
+
+impl PlaylistStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        PlaylistStore { dir: dir.into() }
+    }
+
+    /// `~/.config/mina/playlists` on Linux, or the platform's equivalent.
+    pub fn default_dir() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_default()
+            .join("mina")
+            .join("playlists")
+    }
+
+    /// The names of the saved playlists, in alphabetical order.
+    pub fn list(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = Vec::new();
+        for entry in dir {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort_unstable();
+        Ok(names)
+    }
+
+    /// Save the entries under `name`, replacing any playlist already saved with that name.
+    pub fn save(&self, name: &str, entries: &[PlaylistEntry]) -> Result<(), Box<dyn Error>> {
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.dir)?;
+        fs::write(path, serde_json::to_string_pretty(entries)?)?;
+        Ok(())
+    }
+
+    pub fn load(&self, name: &str) -> Result<Vec<PlaylistEntry>, Box<dyn Error>> {
+        let json = fs::read_to_string(self.path(name)?)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(format!("'{}' can't be used as a playlist name", name).into());
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
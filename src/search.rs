@@ -0,0 +1,368 @@
+use crate::db::Track;
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Title,
+    /// The track artist or the album artist
+    Artist,
+    Album,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    track: u32,
+    field: Field,
+}
+
+/// A word from a search query, optionally limited to one field with `artist:`, `album:` or `title:`.
+#[derive(Debug, PartialEq)]
+struct Term {
+    text: String,
+    field: Option<Field>,
+}
+
+/// Finds tracks by the words in their title, artist and album.
+///
+/// Matching ignores case and accents, and allows prefixes, substrings and
+/// small typos in longer words, with closer matches ranked first. The index
+/// holds each distinct word once, with every suffix of the words sorted so
+/// that the words starting with or containing a query word are a single range.
+/// Only words of about the same length are checked for typos.
+pub struct SearchIndex {
+    /// The library's distinct words, sorted, with the tracks they appear in
+    words: Vec<(String, Vec<Posting>)>,
+    /// Every suffix of every word as a word index and byte offset, in suffix order
+    suffixes: Vec<(u32, u32)>,
+    /// The indexes of the words with each length in characters
+    by_length: Vec<Vec<u32>>,
+    /// Each track's position in artist, album and track order, for ranking equal matches
+    order: Vec<u32>,
+}
+
+impl SearchIndex {
+    pub fn new(tracks: &[Track]) -> Self {
+        let mut words: BTreeMap<String, Vec<Posting>> = BTreeMap::new();
+        for (i, track) in tracks.iter().enumerate() {
+            let fields = [
+                (Field::Title, track.title.as_str()),
+                (Field::Artist, track.artist.as_str()),
+                (Field::Artist, track.album_artist.as_str()),
+                (Field::Album, track.album.as_str()),
+            ];
+            for (field, text) in fields.iter() {
+                for word in split_words(&normalize(text)) {
+                    let postings = words.entry(word.to_string()).or_default();
+                    let posting = Posting {
+                        track: i as u32,
+                        field: *field,
+                    };
+                    // The album artist is often the same as the artist
+                    if !postings
+                        .last()
+                        .is_some_and(|p| p.track == posting.track && p.field == posting.field)
+                    {
+                        postings.push(posting);
+                    }
+                }
+            }
+        }
+        let words: Vec<(String, Vec<Posting>)> = words.into_iter().collect();
+
+        let mut suffixes = Vec::new();
+        let mut by_length: Vec<Vec<u32>> = Vec::new();
+        for (i, (word, _)) in words.iter().enumerate() {
+            let mut length = 0;
+            for (offset, _) in word.char_indices() {
+                suffixes.push((i as u32, offset as u32));
+                length += 1;
+            }
+            if by_length.len() <= length {
+                by_length.resize(length + 1, Vec::new());
+            }
+            by_length[length].push(i as u32);
+        }
+        let suffix = |&(word, offset): &(u32, u32)| &words[word as usize].0[offset as usize..];
+        suffixes.sort_unstable_by(|a, b| suffix(a).cmp(suffix(b)));
+
+        let mut sorted: Vec<usize> = (0..tracks.len()).collect();
+        sorted.sort_by_key(|&i| {
+            let t = &tracks[i];
+            (
+                normalize(t.album_artist_or_artist()),
+                t.year(),
+                normalize(&t.album),
+                t.disc_number,
+                t.track_number,
+            )
+        });
+        let mut order = vec![0; tracks.len()];
+        for (rank, i) in sorted.into_iter().enumerate() {
+            order[i] = rank as u32;
+        }
+        SearchIndex {
+            words,
+            suffixes,
+            by_length,
+            order,
+        }
+    }
+
+    /// The indexes of the tracks which match every word of the query, best match first.
+    pub fn search(&self, query: &str) -> Vec<usize> {
+        let terms = parse_query(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scores: Option<HashMap<u32, u32>> = None;
+        for term in &terms {
+            let mut term_scores: HashMap<u32, u32> = HashMap::new();
+            for (word, score) in self.matching_words(&term.text) {
+                for p in &self.words[word as usize].1 {
+                    if term.field.map_or(true, |f| f == p.field) {
+                        let best = term_scores.entry(p.track).or_insert(0);
+                        *best = (*best).max(score);
+                    }
+                }
+            }
+            scores = Some(match scores {
+                None => term_scores,
+                Some(mut scores) => {
+                    scores.retain(|track, score| match term_scores.get(track) {
+                        Some(s) => {
+                            *score += s;
+                            true
+                        }
+                        None => false,
+                    });
+                    scores
+                }
+            });
+        }
+
+        let mut results: Vec<(u32, u32)> = scores.unwrap_or_default().into_iter().collect();
+        results.sort_by_key(|&(track, score)| (u32::MAX - score, self.order[track as usize]));
+        results
+            .into_iter()
+            .map(|(track, _)| track as usize)
+            .collect()
+    }
+
+    // The words which match a query word, with how well they match. An exact
+    // match scores 4, a prefix 3, a substring 2 and a small typo 1.
+    fn matching_words(&self, term: &str) -> HashMap<u32, u32> {
+        let mut matches = HashMap::new();
+        let start = self.suffixes.partition_point(|s| self.suffix(s) < term);
+        for s in self.suffixes[start..]
+            .iter()
+            .take_while(|s| self.suffix(s).starts_with(term))
+        {
+            let (word, offset) = *s;
+            let score = if offset > 0 {
+                // Short words are found inside too many others
+                if term.len() <= 2 {
+                    continue;
+                }
+                2
+            } else if self.words[word as usize].0.len() == term.len() {
+                4
+            } else {
+                3
+            };
+            let best = matches.entry(word).or_insert(0);
+            *best = score.max(*best);
+        }
+
+        if term.len() > 3 {
+            let max = if term.len() > 7 { 2 } else { 1 };
+            let length = term.chars().count();
+            let candidates = self
+                .by_length
+                .iter()
+                .skip(length.saturating_sub(max))
+                .take(length.min(max) + max + 1)
+                .flatten();
+            for &word in candidates {
+                if let Entry::Vacant(entry) = matches.entry(word) {
+                    if within_distance(&self.words[word as usize].0, term, max) {
+                        entry.insert(1);
+                    }
+                }
+            }
+        }
+        matches
+    }
+
+    fn suffix(&self, &(word, offset): &(u32, u32)) -> &str {
+        &self.words[word as usize].0[offset as usize..]
+    }
+}
+
+/// Lower case with the accents removed, so "Björk" and "bjork" are the same.
+pub fn normalize(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        // "Don't" is searched for as "dont"
+        .filter(|c| *c != '\'' && *c != '’')
+        .collect()
+}
+
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+}
+
+// Words are separated by spaces, and a qualifier applies to the word after
+// it or a quoted phrase, e.g. `artist:"the beatles" help`.
+fn parse_query(query: &str) -> Vec<Term> {
+    let mut terms = Vec::new();
+    let mut rest = query.trim_start();
+    while !rest.is_empty() {
+        let mut field = None;
+        for (prefix, f) in [
+            ("artist:", Field::Artist),
+            ("album:", Field::Album),
+            ("title:", Field::Title),
+        ] {
+            if rest
+                .get(..prefix.len())
+                .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+            {
+                field = Some(f);
+                rest = rest[prefix.len()..].trim_start();
+                break;
+            }
+        }
+        let text = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or("");
+            &quoted[..end]
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let text = &rest[..end];
+            rest = &rest[end..];
+            text
+        };
+        for word in split_words(&normalize(text)) {
+            terms.push(Term {
+                text: word.to_string(),
+                field,
+            });
+        }
+        rest = rest.trim_start();
+    }
+    terms
+}
+
+/// Whether the edit distance between `a` and `b` is at most `max`.
+fn within_distance(a: &str, b: &str, max: usize) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return false;
+    }
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        if current.iter().min().map_or(true, |&d| d > max) {
+            return false;
+        }
+        previous = current;
+    }
+    previous[b.len()] <= max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> Vec<Track> {
+        vec![
+            Track::example(0, "Help!", "The Beatles", "Help!"),
+            Track::example(1, "Helpless", "Neil Young", "Decade"),
+            Track::example(2, "Yesterday", "The Beatles", "Help!"),
+            Track::example(3, "Jóga", "Björk", "Homogenic"),
+            Track::example(4, "Unhelpful", "Someone", "Whatever"),
+            Track::example(5, "Don't Let Me Down", "The Beatles", "Let It Be"),
+            Track::example(6, "Pyramid Song", "Radiohead", "Amnesiac"),
+        ]
+    }
+
+    fn search(query: &str) -> Vec<usize> {
+        SearchIndex::new(&library()).search(query)
+    }
+
+    #[test]
+    fn closer_matches_come_first() {
+        // Exact, then prefix, then substring
+        assert_eq!(search("help"), vec![0, 2, 1, 4]);
+    }
+
+    #[test]
+    fn every_word_has_to_match() {
+        assert_eq!(search("beatles yesterday"), vec![2]);
+        assert_eq!(search("beatles nothing"), Vec::<usize>::new());
+        assert_eq!(search("   "), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn case_accents_and_apostrophes_are_ignored() {
+        assert_eq!(search("BJORK joga"), vec![3]);
+        assert_eq!(search("dont"), vec![5]);
+    }
+
+    #[test]
+    fn longer_words_allow_typos() {
+        assert_eq!(search("yesturday"), vec![2]);
+        assert_eq!(search("pyrmid"), vec![6]);
+        assert_eq!(search("radoihead"), vec![6]);
+        assert_eq!(search("jogo"), vec![3]);
+        // Too far off, or too short to guess at
+        assert_eq!(search("yxstxrdxy"), Vec::<usize>::new());
+        assert_eq!(search("hlp"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn short_words_only_match_the_start() {
+        assert_eq!(search("el"), Vec::<usize>::new());
+        assert_eq!(search("ye"), vec![2]);
+    }
+
+    #[test]
+    fn qualifiers_limit_the_field() {
+        assert_eq!(search("album:help"), vec![0, 2]);
+        assert_eq!(search("title:help"), vec![0, 1, 4]);
+        assert_eq!(search("artist:\"the beatles\" let"), vec![5]);
+        assert_eq!(search("artist:help"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn queries_are_split_into_terms() {
+        let terms = parse_query("Artist:\"The Beatles\" help");
+        let field = |t: &Term| t.field;
+        assert_eq!(
+            terms.iter().map(|t| t.text.as_str()).collect::<Vec<_>>(),
+            vec!["the", "beatles", "help"]
+        );
+        assert_eq!(
+            terms.iter().map(field).collect::<Vec<_>>(),
+            vec![Some(Field::Artist), Some(Field::Artist), None]
+        );
+        // Words shorter than a qualifier in bytes, or split by one mid-character
+        for query in ["Кино", "ビートルズ", "é"] {
+            let terms = parse_query(query);
+            assert_eq!(terms.len(), 1);
+            assert_eq!(terms[0].field, None);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use reqwest::Url;
+use std::error::Error;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const MEDIA_SERVER: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const MEDIA_RENDERER: &str = "urn:schemas-upnp-org:device:MediaRenderer:1";
+/// The port the streamer's HTTP API listens on, which isn't the one it advertises for UPnP
+const NAIM_API_PORT: u16 = 15081;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    MediaServer,
+    Streamer,
+}
+
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub kind: DeviceKind,
+    pub name: String,
+    /// The base URL to use for the device, e.g. "http://192.168.0.123:8200"
+    pub address: String,
+}
+
+/// Look for DLNA servers and Naim streamers on the local network.
+pub fn discover(wait: Duration) -> Result<Vec<Device>, Box<dyn Error>> {
+    discover_at(SSDP_ADDRESS.parse()?, wait)
+}
+
+/// Send the M-SEARCH requests to `target` and collect the devices which answer within `wait`.
+pub fn discover_at(target: SocketAddr, wait: Duration) -> Result<Vec<Device>, Box<dyn Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    for st in &[MEDIA_SERVER, MEDIA_RENDERER] {
+        socket.send_to(m_search(st, wait).as_bytes(), target)?;
+    }
+
+    let mut locations: Vec<(DeviceKind, String)> = Vec::new();
+    let mut buf = [0; 2048];
+    let deadline = Instant::now() + wait;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if remaining == Duration::from_millis(0) {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let response = String::from_utf8_lossy(&buf[..len]);
+        let kind = match header(&response, "ST") {
+            Some(MEDIA_SERVER) => DeviceKind::MediaServer,
+            Some(MEDIA_RENDERER) => DeviceKind::Streamer,
+            _ => continue,
+        };
+        if let Some(location) = header(&response, "LOCATION") {
+            if !locations.iter().any(|(_, l)| l == location) {
+                locations.push((kind, location.to_string()));
+            }
+        }
+    }
+
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()?;
+    let devices = locations
+        .into_iter()
+        .filter_map(|(kind, location)| describe(&client, kind, &location))
+        .collect();
+    Ok(devices)
+}
+
+fn m_search(st: &str, wait: Duration) -> String {
+    format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+        HOST: {}\r\n\
+        MAN: \"ssdp:discover\"\r\n\
+        MX: {}\r\n\
+        ST: {}\r\n\r\n",
+        SSDP_ADDRESS,
+        wait.as_secs().max(1),
+        st
+    )
+}
+
+fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines().find_map(|line| {
+        let (key, value) = line.split_at(line.find(':')?);
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value[1..].trim())
+        } else {
+            None
+        }
+    })
+}
+
+// Fetch the device description for its name. Renderers which aren't made by
+// Naim are dropped since they won't have the streamer API.
+fn describe(
+    client: &reqwest::blocking::Client,
+    kind: DeviceKind,
+    location: &str,
+) -> Option<Device> {
+    let url = Url::parse(location).ok()?;
+    let description = client.get(url.as_str()).send().ok()?.text().ok()?;
+    let host = url.host_str()?;
+    let address = match kind {
+        DeviceKind::MediaServer => format!(
+            "{}://{}:{}",
+            url.scheme(),
+            host,
+            url.port_or_known_default()?
+        ),
+        DeviceKind::Streamer => {
+            let manufacturer = element_text(&description, "manufacturer").unwrap_or("");
+            if !manufacturer.to_lowercase().contains("naim") {
+                return None;
+            }
+            format!("http://{}:{}", host, NAIM_API_PORT)
+        }
+    };
+    let name = element_text(&description, "friendlyName").unwrap_or(host);
+    Some(Device {
+        kind,
+        name: name.to_string(),
+        address,
+    })
+}
+
+// The description documents are small and flat enough that there's no need for an XML parser
+fn element_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find("</")?;
+    Some(xml[start..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const SERVER_DESCRIPTION: &str = "<root><device><friendlyName>MiniDLNA</friendlyName>\
+        <manufacturer>Justin Maggard</manufacturer></device></root>";
+    const NAIM_DESCRIPTION: &str = "<root><device><friendlyName>Living Room</friendlyName>\
+        <manufacturer>Naim Audio Ltd.</manufacturer></device></root>";
+    const OTHER_DESCRIPTION: &str = "<root><device><friendlyName>Television</friendlyName>\
+        <manufacturer>Someone Else</manufacturer></device></root>";
+
+    // Serve each description document at its own path
+    fn serve_descriptions() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let len = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..len]);
+                let body = if request.starts_with("GET /server") {
+                    SERVER_DESCRIPTION
+                } else if request.starts_with("GET /naim") {
+                    NAIM_DESCRIPTION
+                } else {
+                    OTHER_DESCRIPTION
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        port
+    }
+
+    // Answer each M-SEARCH like a server, a Naim streamer and another renderer on the network would
+    fn answer_searches(http_port: u16) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 1024];
+            for _ in 0..2 {
+                let (len, from) = socket.recv_from(&mut buf).unwrap();
+                let request = String::from_utf8_lossy(&buf[..len]).to_string();
+                assert!(request.starts_with("M-SEARCH * HTTP/1.1"));
+                let paths: &[&str] = match header(&request, "ST") {
+                    Some(MEDIA_SERVER) => &["server", "server"],
+                    Some(MEDIA_RENDERER) => &["naim", "other"],
+                    st => panic!("unexpected ST {:?}", st),
+                };
+                for path in paths {
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nST: {}\r\nLOCATION: http://127.0.0.1:{}/{}.xml\r\n\r\n",
+                        header(&request, "ST").unwrap(),
+                        http_port,
+                        path
+                    );
+                    socket.send_to(response.as_bytes(), from).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn finds_devices_which_answer() {
+        let http_port = serve_descriptions();
+        let target = answer_searches(http_port);
+        let mut devices = discover_at(target, Duration::from_millis(500)).unwrap();
+        devices.sort_by_key(|d| d.name.clone());
+        assert_eq!(devices.len(), 2);
+
+        assert_eq!(devices[0].kind, DeviceKind::Streamer);
+        assert_eq!(devices[0].name, "Living Room");
+        assert_eq!(devices[0].address, "http://127.0.0.1:15081");
+
+        assert_eq!(devices[1].kind, DeviceKind::MediaServer);
+        assert_eq!(devices[1].name, "MiniDLNA");
+        assert_eq!(
+            devices[1].address,
+            format!("http://127.0.0.1:{}", http_port)
+        );
+    }
+
+    #[test]
+    fn headers_are_case_insensitive() {
+        let response = "HTTP/1.1 200 OK\r\nst: upnp:rootdevice\r\nLocation:http://a:80/x\r\n";
+        assert_eq!(header(response, "ST"), Some("upnp:rootdevice"));
+        assert_eq!(header(response, "LOCATION"), Some("http://a:80/x"));
+        assert_eq!(header(response, "USN"), None);
+    }
+
+    #[test]
+    fn element_text_is_trimmed() {
+        assert_eq!(
+            element_text("<a><name> Living Room </name></a>", "name"),
+            Some("Living Room")
+        );
+        assert_eq!(element_text("<a></a>", "name"), None);
+    }
+}
@@ -1,21 +1,49 @@
-use crate::app::App;
+use crate::api::{Status, TransportState};
+use crate::app::{App, Mode, View};
+use crate::keys::{Bindings, ACTIONS};
+use crate::ssdp::Device;
+use crate::widgets::StatefulList;
+
+use std::time::Duration;
 
 use tui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Row, Table},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Row, Table, Wrap},
     Frame,
 };
 
 pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     let size = f.size();
+    let screen = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Min(0),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
+        .split(size);
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         //            .margin(1)
-        .constraints([Constraint::Percentage(30), Constraint::Percentage(70)].as_ref())
-        .split(size);
+        .constraints(
+            [
+                Constraint::Percentage(25),
+                Constraint::Percentage(40),
+                Constraint::Percentage(35),
+            ]
+            .as_ref(),
+        )
+        .split(screen[0]);
+    let library_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+        .split(chunks[0]);
 
     let artists: Vec<ListItem> = app
         .artists
@@ -24,44 +52,344 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         .map(|i| ListItem::new(vec![Spans::from(Span::raw(i.name))]))
         .collect();
 
-    let selected_style = Style::default().add_modifier(Modifier::BOLD);
+    let mut selected_style = Style::default().add_modifier(Modifier::BOLD);
+    if let Some(color) = app.colors.selected {
+        selected_style = selected_style.fg(color);
+    }
 
     let artists = List::new(artists)
         .block(Block::default().borders(Borders::ALL).title("Artists"))
         .highlight_style(selected_style);
 
-    let rows = app
-        .tracks
+    let albums: Vec<ListItem> = app
+        .albums
+        .items
         .iter()
-        .map(|i| Row::new(vec![i.title.as_str(), i.album.as_str()]));
+        .map(|a| {
+            let text = match a.year {
+                Some(year) => format!("{} {} ({})", year, a.name, a.track_count),
+                None => format!("{} ({})", a.name, a.track_count),
+            };
+            ListItem::new(vec![Spans::from(Span::raw(text))])
+        })
+        .collect();
+
+    let albums = List::new(albums)
+        .block(Block::default().borders(Borders::ALL).title("Albums"))
+        .highlight_style(selected_style);
+
+    let mut previous_artist = None;
+    let album_rows = app.all_albums.items.iter().map(|a| {
+        // Only name the album artist on the first album of each group
+        let artist = if previous_artist == Some(a.artist) {
+            ""
+        } else {
+            a.artist
+        };
+        previous_artist = Some(a.artist);
+        Row::new(vec![
+            artist.to_string(),
+            a.year.unwrap_or("").to_string(),
+            a.name.to_string(),
+        ])
+    });
+    let albums_table = Table::new(album_rows)
+        .header(Row::new(vec!["Album Artist", "Year", "Album"]))
+        .block(Block::default().borders(Borders::ALL).title("Albums"))
+        .highlight_style(selected_style)
+        .widths(&[
+            Constraint::Percentage(40),
+            Constraint::Length(4),
+            Constraint::Percentage(50),
+        ]);
+
+    let (headers, widths) = match app.view {
+        View::Artists => (
+            vec!["#", "Title"],
+            vec![Constraint::Length(3), Constraint::Percentage(90)],
+        ),
+        View::Albums => (
+            vec!["#", "Title", "Artist"],
+            vec![
+                Constraint::Length(3),
+                Constraint::Percentage(55),
+                Constraint::Percentage(35),
+            ],
+        ),
+    };
+    let rows = app.tracks.iter().map(|i| {
+        let mut cells = vec![i.track_number.to_string(), i.title.clone()];
+        if app.view == View::Albums {
+            cells.push(i.artist.clone());
+        }
+        Row::new(cells)
+    });
+    let tracks_title = match app.match_counter() {
+        Some(counter) => format!("Tracks ({})", counter),
+        None => "Tracks".to_string(),
+    };
     let tracks_table = Table::new(rows)
-        .header(Row::new(vec!["Title", "Album"]))
-        .block(Block::default().borders(Borders::ALL).title("Tracks"))
+        .header(Row::new(headers))
+        .block(Block::default().borders(Borders::ALL).title(tracks_title))
         .highlight_style(selected_style)
-        .widths(&[Constraint::Percentage(50), Constraint::Percentage(50)]);
-    f.render_stateful_widget(artists, chunks[0], &mut app.artists.state);
-    f.render_stateful_widget(tracks_table, chunks[1], &mut app.track_list_state);
-
-    if app.mode == crate::app::Mode::SearchInput {
-        let search_box = Block::default().borders(Borders::ALL);
-        let search_box_area = centered_rect(60, 20, size);
-        f.render_widget(Clear, search_box_area);
-        f.render_widget(search_box, search_box_area);
-        let input = Paragraph::new(app.search_input.as_ref())
-            .block(Block::default().title("Search").borders(Borders::ALL));
-        let input_area = Rect::new(
-            search_box_area.x + 4,
-            search_box_area.y + search_box_area.height / 2 - 1,
-            search_box_area.width - 8,
-            3,
-        );
-        f.render_widget(input, input_area);
-
-        f.set_cursor(
-            input_area.x + app.search_input.len() as u16 + 1,
-            input_area.y + 1,
-        )
+        .widths(&widths);
+
+    let results_title = format!(
+        "Search results ({})",
+        app.match_counter().unwrap_or_default()
+    );
+    let results = app
+        .search_results()
+        .map(|t| Row::new(vec![t.title.as_str(), t.artist.as_str(), t.album.as_str()]));
+    let results_table = Table::new(results)
+        .header(Row::new(vec!["Title", "Artist", "Album"]))
+        .block(Block::default().borders(Borders::ALL).title(results_title))
+        .highlight_style(selected_style)
+        .widths(&[
+            Constraint::Percentage(40),
+            Constraint::Percentage(30),
+            Constraint::Percentage(30),
+        ]);
+
+    let current_style = Style::default().fg(app.colors.current);
+    let entries = app.playlist.iter().map(|e| {
+        let row = Row::new(vec![
+            e.name.as_str(),
+            e.artistName.as_str(),
+            e.albumName.as_str(),
+        ]);
+        if Some(&e.ussi) == app.current_entry.as_ref() {
+            row.style(current_style)
+        } else {
+            row
+        }
+    });
+    let playlist_table = Table::new(entries)
+        .header(Row::new(vec!["Name", "Artist", "Album"]))
+        .block(Block::default().borders(Borders::ALL).title("Playlist"))
+        .highlight_style(selected_style)
+        .widths(&[
+            Constraint::Percentage(40),
+            Constraint::Percentage(30),
+            Constraint::Percentage(30),
+        ]);
+    match app.view {
+        View::Artists => {
+            f.render_stateful_widget(artists, library_chunks[0], &mut app.artists.state);
+            f.render_stateful_widget(albums, library_chunks[1], &mut app.albums.state);
+        }
+        View::Albums => {
+            f.render_stateful_widget(albums_table, chunks[0], &mut app.all_albums.state);
+        }
+    }
+    if app.showing_results() {
+        f.render_stateful_widget(results_table, chunks[1], &mut app.results_state);
+    } else {
+        f.render_stateful_widget(tracks_table, chunks[1], &mut app.track_list_state);
+    }
+    f.render_stateful_widget(playlist_table, chunks[2], &mut app.playlist_state);
+    let status = status_bar(app.status.as_ref(), app.volume, app.bindings.pending());
+    f.render_widget(status, screen[1]);
+    if let Some(message) = &app.message {
+        let message =
+            Paragraph::new(message.as_str()).style(Style::default().fg(app.colors.message));
+        f.render_widget(message, screen[2]);
+    }
+
+    match app.mode {
+        Mode::SearchInput => {
+            // Along the bottom, to keep the matches in view as they're found
+            let area = Rect::new(
+                screen[0].x,
+                screen[0].bottom().saturating_sub(3),
+                screen[0].width,
+                screen[0].height.min(3),
+            );
+            let input = Paragraph::new(app.search_input.as_str())
+                .block(Block::default().title("Search").borders(Borders::ALL));
+            f.render_widget(Clear, area);
+            f.render_widget(input, area);
+            f.set_cursor(
+                area.x + app.search_input.chars().count() as u16 + 1,
+                area.y + 1,
+            );
+        }
+        Mode::PlaylistName => draw_input(f, "Save playlist as", &app.input),
+        Mode::ImportPath => draw_input(f, "Add tracks from M3U or PLS file", &app.input),
+        Mode::ExportPath => draw_input(f, "Export playlist to M3U8 file", &app.input),
+        Mode::PlaylistPicker => {
+            let area = centered_rect(40, 40, size);
+            let items: Vec<ListItem> = app
+                .saved_playlists
+                .items
+                .iter()
+                .map(|name| ListItem::new(vec![Spans::from(Span::raw(name.as_str()))]))
+                .collect();
+            let list = List::new(items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Add saved playlist"),
+                )
+                .highlight_style(selected_style);
+            f.render_widget(Clear, area);
+            f.render_stateful_widget(list, area, &mut app.saved_playlists.state);
+        }
+        Mode::Help => {
+            let area = centered_rect(60, 80, size);
+            let lines = help_lines(&app.bindings);
+            // Stop scrolling once the last line is in view
+            let hidden = (lines.len() as u16).saturating_sub(area.height.saturating_sub(2));
+            app.help_scroll = app.help_scroll.min(hidden);
+            let help = Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title("Keys"))
+                .scroll((app.help_scroll, 0));
+            f.render_widget(Clear, area);
+            f.render_widget(help, area);
+        }
+        Mode::Confirm(action) => {
+            let area = centered_rect(40, 20, size);
+            let text = format!("{}? (y/n)", action.description());
+            let question = Paragraph::new(text)
+                .alignment(Alignment::Center)
+                .wrap(Wrap { trim: true })
+                .block(Block::default().borders(Borders::ALL).title("Confirm"));
+            f.render_widget(Clear, area);
+            f.render_widget(question, area);
+        }
+        Mode::Normal => {}
+    }
+}
+
+/// A popup with a single line of text input.
+fn draw_input<B: Backend>(f: &mut Frame<B>, title: &str, text: &str) {
+    let input_box = Block::default().borders(Borders::ALL);
+    let input_box_area = centered_rect(60, 20, f.size());
+    f.render_widget(Clear, input_box_area);
+    f.render_widget(input_box, input_box_area);
+    let input = Paragraph::new(text).block(Block::default().title(title).borders(Borders::ALL));
+    let input_area = Rect::new(
+        input_box_area.x + 4,
+        input_box_area.y + input_box_area.height / 2 - 1,
+        input_box_area.width - 8,
+        3,
+    );
+    f.render_widget(input, input_area);
+
+    f.set_cursor(
+        input_area.x + text.chars().count() as u16 + 1,
+        input_area.y + 1,
+    )
+}
+
+/// Every key binding, under a heading for each group of actions.
+fn help_lines(bindings: &Bindings) -> Vec<Spans<'static>> {
+    let mut lines = Vec::new();
+    let mut category = None;
+    for info in ACTIONS {
+        let keys = bindings.keys_for(info.action);
+        if keys.is_empty() {
+            continue;
+        }
+        let action_category = info.action.category();
+        if category != Some(action_category) {
+            if category.is_some() {
+                lines.push(Spans::default());
+            }
+            lines.push(Spans::from(Span::styled(
+                action_category.title(),
+                Style::default().add_modifier(Modifier::BOLD),
+            )));
+            category = Some(action_category);
+        }
+        lines.push(Spans::from(format!(
+            "  {:<18} {}",
+            keys.join(", "),
+            info.action.description()
+        )));
     }
+    lines
+}
+
+pub fn draw_device_picker<B: Backend>(
+    f: &mut Frame<B>,
+    title: &str,
+    devices: &mut StatefulList<Device>,
+) {
+    let area = centered_rect(60, 40, f.size());
+    let items: Vec<ListItem> = devices
+        .items
+        .iter()
+        .map(|d| {
+            ListItem::new(vec![Spans::from(Span::raw(format!(
+                "{} ({})",
+                d.name, d.address
+            )))])
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD));
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut devices.state);
+}
+
+fn status_bar(
+    status: Option<&Status>,
+    volume: Option<u8>,
+    pending_keys: Option<String>,
+) -> Paragraph<'static> {
+    let mut spans = Vec::new();
+    // The start of a key sequence, such as the first "g" of "gg"
+    if let Some(keys) = pending_keys {
+        spans.push(Span::styled(
+            format!(" {} ", keys),
+            Style::default().add_modifier(Modifier::REVERSED),
+        ));
+        spans.push(Span::raw(" "));
+    }
+    let status = match status {
+        Some(status) => status,
+        None => return Paragraph::new(Spans::from(spans)),
+    };
+    match &status.now_playing {
+        Some(np) => {
+            let state = match np.state() {
+                Some(TransportState::Playing) => "▶",
+                Some(TransportState::Paused) => "⏸",
+                Some(TransportState::Stopped) | None => "■",
+            };
+            spans.push(Span::raw(format!("{} ", state)));
+            let track: Vec<&str> = [&np.title, &np.artistName, &np.albumName]
+                .iter()
+                .filter_map(|s| s.as_deref())
+                .filter(|s| !s.is_empty())
+                .collect();
+            spans.push(Span::styled(
+                track.join(" - "),
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+            if let Some(position) = np.position() {
+                let total = np.duration().map(format_time).unwrap_or_default();
+                spans.push(Span::raw(format!(
+                    "  {} / {}",
+                    format_time(position),
+                    total
+                )));
+            }
+        }
+        None => spans.push(Span::raw("Streamer not responding")),
+    }
+    // The app's volume, which follows key presses before the streamer reports them
+    if let Some(volume) = volume {
+        spans.push(Span::raw(format!("  Vol {}", volume)));
+    }
+    Paragraph::new(Spans::from(spans))
+}
+
+fn format_time(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
 }
 
 /// helper function to create a centered rect using up
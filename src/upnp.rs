@@ -0,0 +1,517 @@
+use crate::db::{Track, TrackSource};
+
+use roxmltree::{Document, Node};
+use std::collections::HashSet;
+use std::error::Error;
+
+/// MiniDLNA's control URL for the ContentDirectory service
+const CONTROL_PATH: &str = "/ctl/ContentDir";
+const SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const PAGE_SIZE: u32 = 500;
+const AUDIO_ITEMS: &str = "upnp:class derivedfrom \"object.item.audioItem\"";
+
+const DIDL_NS: &str = "urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const UPNP_NS: &str = "urn:schemas-upnp-org:metadata-1-0/upnp/";
+
+/// Reads the library from the DLNA server's ContentDirectory service, so
+/// there's no need to dump the server's database first.
+pub struct UpnpSource {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+/// One page of DIDL-Lite results from a `Browse` or `Search` call.
+pub struct Page {
+    pub tracks: Vec<Track>,
+    pub containers: Vec<String>,
+    pub number_returned: u32,
+    pub total_matches: u32,
+}
+
+impl UpnpSource {
+    pub fn new(url: &str) -> Self {
+        UpnpSource {
+            url: url.to_string(),
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+
+    /// List the direct children of a container.
+    pub fn browse(&self, object_id: &str, start: u32) -> Result<Page, Box<dyn Error>> {
+        self.call(
+            "Browse",
+            &[
+                ("ObjectID", object_id),
+                ("BrowseFlag", "BrowseDirectChildren"),
+                ("Filter", "*"),
+                ("StartingIndex", &start.to_string()),
+                ("RequestedCount", &PAGE_SIZE.to_string()),
+                ("SortCriteria", ""),
+            ],
+        )
+    }
+
+    /// Find everything below a container which matches the search criteria.
+    pub fn search(
+        &self,
+        container_id: &str,
+        criteria: &str,
+        start: u32,
+    ) -> Result<Page, Box<dyn Error>> {
+        self.call(
+            "Search",
+            &[
+                ("ContainerID", container_id),
+                ("SearchCriteria", criteria),
+                ("Filter", "*"),
+                ("StartingIndex", &start.to_string()),
+                ("RequestedCount", &PAGE_SIZE.to_string()),
+                ("SortCriteria", ""),
+            ],
+        )
+    }
+
+    fn call(&self, action: &str, args: &[(&str, &str)]) -> Result<Page, Box<dyn Error>> {
+        let mut body = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+            <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+            s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body>",
+        );
+        body.push_str(&format!("<u:{} xmlns:u=\"{}\">", action, SERVICE_TYPE));
+        for (name, value) in args {
+            body.push_str(&format!("<{0}>{1}</{0}>", name, escape(value)));
+        }
+        body.push_str(&format!("</u:{}></s:Body></s:Envelope>", action));
+
+        let response = self
+            .client
+            .post(format!("{}{}", self.url, CONTROL_PATH).as_str())
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", format!("\"{}#{}\"", SERVICE_TYPE, action))
+            .body(body)
+            .send()?
+            .error_for_status()?
+            .text()?;
+        parse_response(&response)
+    }
+
+    // Search for all the audio items. This is much quicker than walking the
+    // containers, but not every server supports it.
+    fn search_tracks(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        let mut tracks = Vec::new();
+        // Counted separately from the tracks, since some items are skipped
+        let mut start = 0;
+        loop {
+            let page = self.search("0", AUDIO_ITEMS, start)?;
+            start += page.number_returned;
+            tracks.extend(page.tracks);
+            if page.number_returned == 0 || start >= page.total_matches {
+                return Ok(tracks);
+            }
+        }
+    }
+
+    // Walk the whole container tree. The same track shows up in several
+    // places (by artist, by album, by folder...) so duplicates are dropped.
+    fn browse_tracks(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![String::from("0")];
+        while let Some(container) = pending.pop() {
+            let mut start = 0;
+            loop {
+                let page = self.browse(&container, start)?;
+                start += page.number_returned;
+                pending.extend(page.containers);
+                for track in page.tracks {
+                    if seen.insert(track.id) {
+                        tracks.push(track);
+                    }
+                }
+                if page.number_returned == 0 || start >= page.total_matches {
+                    break;
+                }
+            }
+        }
+        Ok(tracks)
+    }
+}
+
+impl TrackSource for UpnpSource {
+    fn read_tracks(&self) -> Result<Vec<Track>, Box<dyn Error>> {
+        let search_error = match self.search_tracks() {
+            Ok(tracks) => return Ok(tracks),
+            Err(e) => e,
+        };
+        self.browse_tracks().map_err(|e| {
+            format!(
+                "Failed to read tracks from {}: {} (searching failed first: {})",
+                self.url, e, search_error
+            )
+            .into()
+        })
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn parse_response(response: &str) -> Result<Page, Box<dyn Error>> {
+    let doc = Document::parse(response)?;
+    let field = |name: &str| {
+        doc.descendants()
+            .find(|n| n.tag_name().name() == name)
+            .and_then(|n| n.text())
+    };
+    if let Some(fault) = field("errorDescription").or_else(|| field("faultstring")) {
+        return Err(fault.into());
+    }
+    let result = field("Result").ok_or("No 'Result' in response")?;
+    let number_returned = field("NumberReturned").unwrap_or("0").parse()?;
+    let total_matches = field("TotalMatches").unwrap_or("0").parse()?;
+    let (tracks, containers) = parse_didl(result)?;
+    Ok(Page {
+        tracks,
+        containers,
+        number_returned,
+        total_matches,
+    })
+}
+
+/// Turn a DIDL-Lite document into tracks, along with the ids of any containers it lists.
+pub fn parse_didl(didl: &str) -> Result<(Vec<Track>, Vec<String>), Box<dyn Error>> {
+    let doc = Document::parse(didl)?;
+    let mut tracks = Vec::new();
+    let mut containers = Vec::new();
+
+    for node in doc.root_element().children().filter(|n| n.is_element()) {
+        if node.has_tag_name((DIDL_NS, "container")) {
+            if let Some(id) = node.attribute("id") {
+                containers.push(id.to_string());
+            }
+        } else if node.has_tag_name((DIDL_NS, "item")) {
+            if let Some(track) = track_from_item(node) {
+                tracks.push(track);
+            }
+        }
+    }
+    Ok((tracks, containers))
+}
+
+// Items which aren't audio, or which don't have a MiniDLNA style
+// `/MediaItems/<id>.<ext>` resource, are skipped.
+fn track_from_item(item: Node) -> Option<Track> {
+    let class = child_text(item, UPNP_NS, "class")?;
+    if !class.starts_with("object.item.audioItem") {
+        return None;
+    }
+    let res = item.children().find(|n| n.has_tag_name((DIDL_NS, "res")))?;
+    let uri = res.text()?;
+    let id = uri
+        .rsplit("/MediaItems/")
+        .next()?
+        .split('.')
+        .next()?
+        .parse()
+        .ok()?;
+    // protocolInfo looks like "http-get:*:audio/x-flac:*"
+    let mime_type = res.attribute("protocolInfo")?.split(':').nth(2)?;
+    // and album art like "http://host:8200/AlbumArt/1654-3590.jpg"
+    let album_art_id = child_text(item, UPNP_NS, "albumArtURI")
+        .and_then(|uri| uri.rsplit("/AlbumArt/").next())
+        .and_then(|name| name.split('-').next())
+        .and_then(|art| art.parse().ok())
+        .unwrap_or(0);
+    let album_artist = item
+        .children()
+        .find(|n| n.has_tag_name((UPNP_NS, "artist")) && n.attribute("role") == Some("AlbumArtist"))
+        .and_then(|n| n.text())
+        .or_else(|| child_text(item, UPNP_NS, "albumArtist"));
+    let artist = item
+        .children()
+        .find(|n| n.has_tag_name((UPNP_NS, "artist")) && n.attribute("role").is_none())
+        .and_then(|n| n.text())
+        .or_else(|| child_text(item, DC_NS, "creator"));
+    let text = |value: Option<&str>| value.unwrap_or("").to_string();
+    let number = |name: &str| {
+        child_text(item, UPNP_NS, name)
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0)
+    };
+
+    Some(Track {
+        id,
+        title: text(child_text(item, DC_NS, "title")),
+        artist: text(artist),
+        album_artist: text(album_artist),
+        album: text(child_text(item, UPNP_NS, "album")),
+        genre: text(child_text(item, UPNP_NS, "genre")),
+        album_art_id,
+        track_number: number("originalTrackNumber"),
+        disc_number: number("originalDiscNumber") as u8,
+        track_id: item.attribute("id")?.to_string(),
+        duration: text(res.attribute("duration")),
+        date: text(child_text(item, DC_NS, "date")),
+        mime_type: mime_type.to_string(),
+    })
+}
+
+fn child_text<'a>(node: Node<'a, '_>, namespace: &str, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name((namespace, name)))
+        .and_then(|n| n.text())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const DIDL: &str = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">
+<container id="1$4" parentID="1" restricted="1"><dc:title>Album</dc:title><upnp:class>object.container.storageFolder</upnp:class></container>
+<item id="64$0$1" parentID="64$0" restricted="1">
+<dc:title>Hunter</dc:title>
+<upnp:class>object.item.audioItem.musicTrack</upnp:class>
+<dc:creator>Björk</dc:creator>
+<upnp:artist>Björk</upnp:artist>
+<upnp:artist role="AlbumArtist">Björk &amp; Friends</upnp:artist>
+<upnp:album>Homogenic</upnp:album>
+<upnp:genre>Pop</upnp:genre>
+<dc:date>1997-09-22</dc:date>
+<upnp:originalTrackNumber>1</upnp:originalTrackNumber>
+<upnp:originalDiscNumber>2</upnp:originalDiscNumber>
+<upnp:albumArtURI>http://10.0.0.2:8200/AlbumArt/1654-3590.jpg</upnp:albumArtURI>
+<res duration="0:04:15.000" protocolInfo="http-get:*:audio/x-flac:*">http://10.0.0.2:8200/MediaItems/3590.flac</res>
+</item>
+<item id="64$0$2" parentID="64$0" restricted="1">
+<dc:title>Holiday snap</dc:title>
+<upnp:class>object.item.imageItem.photo</upnp:class>
+<res protocolInfo="http-get:*:image/jpeg:*">http://10.0.0.2:8200/MediaItems/12.jpg</res>
+</item>
+</DIDL-Lite>"#;
+
+    fn soap(result: &str, returned: u32, total: u32) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>
+<u:BrowseResponse xmlns:u="urn:schemas-upnp-org:service:ContentDirectory:1">
+<Result>{}</Result><NumberReturned>{}</NumberReturned><TotalMatches>{}</TotalMatches><UpdateID>1</UpdateID>
+</u:BrowseResponse></s:Body></s:Envelope>"#,
+            escape(result),
+            returned,
+            total
+        )
+    }
+
+    #[test]
+    fn didl_items_become_tracks() {
+        let (tracks, containers) = parse_didl(DIDL).unwrap();
+        assert_eq!(containers, vec!["1$4"]);
+        assert_eq!(tracks.len(), 1);
+        let track = &tracks[0];
+        assert_eq!(track.id, 3590);
+        assert_eq!(track.title, "Hunter");
+        assert_eq!(track.artist, "Björk");
+        assert_eq!(track.album_artist, "Björk & Friends");
+        assert_eq!(track.album, "Homogenic");
+        assert_eq!(track.genre, "Pop");
+        assert_eq!(track.album_art_id, 1654);
+        assert_eq!(track.track_number, 1);
+        assert_eq!(track.disc_number, 2);
+        assert_eq!(track.track_id, "64$0$1");
+        assert_eq!(track.duration, "0:04:15.000");
+        assert_eq!(track.date, "1997-09-22");
+        assert_eq!(track.mime_type, "audio/x-flac");
+    }
+
+    #[test]
+    fn creator_stands_in_for_a_missing_artist() {
+        let didl = DIDL
+            .replace("<upnp:artist>Björk</upnp:artist>", "")
+            .replace(
+                "<dc:creator>Björk</dc:creator>",
+                "<dc:creator>Someone</dc:creator>",
+            );
+        let (tracks, _) = parse_didl(&didl).unwrap();
+        assert_eq!(tracks[0].artist, "Someone");
+    }
+
+    #[test]
+    fn soap_response_holds_a_page() {
+        let page = parse_response(&soap(DIDL, 3, 10)).unwrap();
+        assert_eq!(page.tracks.len(), 1);
+        assert_eq!(page.containers, vec!["1$4"]);
+        assert_eq!(page.number_returned, 3);
+        assert_eq!(page.total_matches, 10);
+    }
+
+    const FAULT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><s:Fault>
+<faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>
+<detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>708</errorCode>
+<errorDescription>Unsupported or invalid search criteria</errorDescription></UPnPError></detail>
+</s:Fault></s:Body></s:Envelope>"#;
+
+    // A MiniDLNA style audio item, whose id doesn't fit a track if it's over 65535
+    fn item(id: u32) -> String {
+        format!(
+            r#"<item id="64$0${0}" parentID="64$0" restricted="1"><dc:title>Track {0}</dc:title>
+<upnp:class>object.item.audioItem.musicTrack</upnp:class>
+<res protocolInfo="http-get:*:audio/x-flac:*">http://10.0.0.2:8200/MediaItems/{0}.flac</res></item>"#,
+            id
+        )
+    }
+
+    fn container(id: &str) -> String {
+        format!(
+            r#"<container id="{}" parentID="0" restricted="1"><upnp:class>object.container</upnp:class></container>"#,
+            id
+        )
+    }
+
+    fn didl(children: &[String]) -> String {
+        format!(
+            r#"<DIDL-Lite xmlns="{}" xmlns:dc="{}" xmlns:upnp="{}">{}</DIDL-Lite>"#,
+            DIDL_NS,
+            DC_NS,
+            UPNP_NS,
+            children.concat()
+        )
+    }
+
+    // The text of the first element with this name in a request
+    fn element<'a>(request: &'a str, name: &str) -> &'a str {
+        let start = request.find(&format!("<{}>", name)).unwrap() + name.len() + 2;
+        let end = request.find(&format!("</{}>", name)).unwrap();
+        &request[start..end]
+    }
+
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let len = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..len]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            let end = match text.find("\r\n\r\n") {
+                Some(end) => end + 4,
+                None if len > 0 => continue,
+                None => return text,
+            };
+            let length: usize = text[..end]
+                .lines()
+                .find_map(|line| {
+                    let line = line.to_ascii_lowercase();
+                    line.strip_prefix("content-length:")
+                        .map(|n| n.trim().parse().unwrap())
+                })
+                .unwrap_or(0);
+            if len == 0 || request.len() >= end + length {
+                return text;
+            }
+        }
+    }
+
+    // A ContentDirectory service which answers each call with `respond(action,
+    // object id, starting index)`, keeping the requests it was sent. It gives up
+    // with a fault after 20 calls, so a client which loops fails rather than hangs.
+    fn serve(respond: fn(&str, &str, u32) -> (u16, String)) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+                let (status, body) = if log.lock().unwrap().len() >= 20 {
+                    (500, FAULT.to_string())
+                } else if request.contains("#Search\"") {
+                    let start = element(&request, "StartingIndex").parse().unwrap();
+                    respond("Search", element(&request, "ContainerID"), start)
+                } else {
+                    let start = element(&request, "StartingIndex").parse().unwrap();
+                    respond("Browse", element(&request, "ObjectID"), start)
+                };
+                log.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn ids(tracks: &[Track]) -> Vec<u16> {
+        let mut ids: Vec<u16> = tracks.iter().map(|t| t.id).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn soap_fault_is_an_error() {
+        let error = parse_response(FAULT).err().unwrap();
+        assert_eq!(error.to_string(), "Unsupported or invalid search criteria");
+    }
+
+    #[test]
+    fn searching_pages_past_skipped_items() {
+        let (url, requests) = serve(|action, _, start| match (action, start) {
+            ("Search", 0) => (200, soap(&didl(&[item(1), item(70000)]), 2, 3)),
+            ("Search", 2) => (200, soap(&didl(&[item(2)]), 1, 3)),
+            _ => (500, FAULT.to_string()),
+        });
+        let tracks = UpnpSource::new(&url).read_tracks().unwrap();
+        assert_eq!(ids(&tracks), vec![1, 2]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let request = &requests[1];
+        assert!(request.starts_with("POST /ctl/ContentDir HTTP/1.1"));
+        assert!(request.contains(&format!("\"{}#Search\"", SERVICE_TYPE)));
+        assert_eq!(element(request, "ContainerID"), "0");
+        assert_eq!(
+            element(request, "SearchCriteria"),
+            "upnp:class derivedfrom &quot;object.item.audioItem&quot;"
+        );
+        assert_eq!(element(request, "StartingIndex"), "2");
+    }
+
+    #[test]
+    fn browsing_is_the_fallback_when_searching_fails() {
+        let (url, requests) = serve(|action, object, start| match (action, object, start) {
+            ("Browse", "0", 0) => (200, soap(&didl(&[container("1"), container("2")]), 2, 2)),
+            ("Browse", "1", 0) => (200, soap(&didl(&[item(1), item(70000)]), 2, 3)),
+            ("Browse", "1", 2) => (200, soap(&didl(&[item(2)]), 1, 3)),
+            // The same track listed in another container
+            ("Browse", "2", 0) => (200, soap(&didl(&[item(1)]), 1, 1)),
+            _ => (500, FAULT.to_string()),
+        });
+        let tracks = UpnpSource::new(&url).read_tracks().unwrap();
+        assert_eq!(ids(&tracks), vec![1, 2]);
+        // The failed search, then every page of every container
+        assert_eq!(requests.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn both_errors_are_reported() {
+        let (url, _) = serve(|_, _, _| (500, FAULT.to_string()));
+        let error = UpnpSource::new(&url).read_tracks().err().unwrap();
+        let error = error.to_string();
+        assert!(error.starts_with(&format!("Failed to read tracks from {}", url)));
+        assert!(error.contains("searching failed first"), "{}", error);
+    }
+}
@@ -1,4 +1,4 @@
-use tui::widgets::ListState;
+use tui::widgets::{ListState, TableState};
 
 pub struct StatefulList<T> {
     pub state: ListState,
@@ -16,32 +16,65 @@ impl<T> StatefulList<T> {
     }
 
     pub fn next(&mut self, n: usize) {
-        let i = match self.state.selected() {
-            Some(i) => {
-                if i + n > self.items.len() - 1 {
-                    0
-                } else {
-                    i + n
-                }
-            }
-            None => 0,
-        };
+        let i = next_index(self.state.selected(), n, self.items.len());
         self.state.select(Some(i));
     }
 
     pub fn previous(&mut self, n: usize) {
-        let i = match self.state.selected() {
-            Some(i) => {
-                if i == 0 {
-                    self.items.len() - 1
-                } else if i < n {
-                    0
-                } else {
-                    i - n
-                }
-            }
-            None => 0,
+        let i = previous_index(self.state.selected(), n, self.items.len());
+        self.state.select(Some(i));
+    }
+}
+
+/// The same as `StatefulList`, for items rendered in a `Table`.
+pub struct StatefulTable<T> {
+    pub state: TableState,
+    pub items: Vec<T>,
+}
+
+impl<T> StatefulTable<T> {
+    pub fn with_items(items: Vec<T>) -> StatefulTable<T> {
+        let mut table = StatefulTable {
+            state: TableState::default(),
+            items,
         };
+        table.state.select(Some(0));
+        table
+    }
+
+    pub fn next(&mut self, n: usize) {
+        let i = next_index(self.state.selected(), n, self.items.len());
         self.state.select(Some(i));
     }
+
+    pub fn previous(&mut self, n: usize) {
+        let i = previous_index(self.state.selected(), n, self.items.len());
+        self.state.select(Some(i));
+    }
+}
+
+fn next_index(selected: Option<usize>, n: usize, len: usize) -> usize {
+    match selected {
+        Some(i) => {
+            if i + n > len.saturating_sub(1) {
+                0
+            } else {
+                i + n
+            }
+        }
+        None => 0,
+    }
+}
+
+fn previous_index(selected: Option<usize>, n: usize, len: usize) -> usize {
+    match selected {
+        Some(i) => {
+            if i == 0 {
+                len.saturating_sub(1)
+            } else {
+                i.saturating_sub(n)
+            }
+        }
+        None => 0,
+    }
 }
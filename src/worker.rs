@@ -0,0 +1,130 @@
+use crate::api::{Api, ApiError, Playlist, PlaylistEntry, Status};
+use crate::db::Track;
+use crate::events::Event;
+
+use serde::Deserialize;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use termion::event::Key;
+
+/// Where added tracks go in the play queue
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    End,
+    Next,
+    /// Clear the queue first, then start playing the new tracks
+    Replace,
+}
+
+/// A request for the worker thread to make to the streamer
+pub enum Command {
+    RefreshPlaylist,
+    Play,
+    PlayEntry(String),
+    AddTracks(Vec<Track>, Position),
+    /// Append entries taken from a queue, e.g. a saved playlist
+    QueueEntries(Vec<PlaylistEntry>),
+    ClearPlaylist,
+    /// Replace the queue with edited entries, jumping back to `current` if it's given
+    EditPlaylist {
+        entries: Vec<PlaylistEntry>,
+        current: Option<usize>,
+    },
+    SetVolume(u8),
+    VolumeUp,
+    VolumeDown,
+    TogglePlayPause,
+    Stop,
+    Next,
+    Previous,
+    PowerOn,
+    Suspend,
+}
+
+/// The result of a command, sent back to the app as an `Event::Api`
+pub enum Reply {
+    Playlist(Playlist),
+    /// The volume after a volume command, or why it couldn't be changed
+    Volume(Result<u8, ApiError>),
+    Status(Status),
+    /// The queue after a command which changes it, or why it couldn't be changed
+    Edited(Result<Playlist, ApiError>),
+    Error(ApiError),
+}
+
+/// Run the streamer requests on their own thread, so a slow or unreachable
+/// streamer never holds up the UI. Commands are run in the order they're sent.
+pub fn spawn(api: Api, events: Sender<Event<Key>>) -> Sender<Command> {
+    let (tx, rx) = mpsc::channel::<Command>();
+    thread::spawn(move || {
+        for command in rx {
+            let changes_queue = matches!(
+                command,
+                Command::PlayEntry(_)
+                    | Command::AddTracks(..)
+                    | Command::QueueEntries(_)
+                    | Command::ClearPlaylist
+                    | Command::EditPlaylist { .. }
+            );
+            // The app waits for the queue after each of these before editing it again
+            let reply = if changes_queue {
+                Some(Reply::Edited(
+                    run(&api, command).and_then(|_| api.get_playlist()),
+                ))
+            } else {
+                run(&api, command).unwrap_or_else(|e| Some(Reply::Error(e)))
+            };
+            if let Some(reply) = reply {
+                if events.send(Event::Api(reply)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    tx
+}
+
+fn run(api: &Api, command: Command) -> Result<Option<Reply>, ApiError> {
+    match command {
+        Command::RefreshPlaylist => return api.get_playlist().map(|p| Some(Reply::Playlist(p))),
+        Command::Play => api.play()?,
+        Command::PlayEntry(ussi) => api.play_entry(ussi)?,
+        Command::AddTracks(tracks, position) => {
+            let tracks: Vec<&Track> = tracks.iter().collect();
+            match position {
+                Position::End => api.queue_tracks(&tracks)?,
+                Position::Next => api.queue_tracks_next(&tracks)?,
+                Position::Replace => api.replace_and_play(&tracks)?,
+            }
+        }
+        Command::QueueEntries(entries) => api.queue_entries(&entries)?,
+        Command::ClearPlaylist => api.clear_playlist()?,
+        Command::EditPlaylist { entries, current } => api.replace_playlist(&entries, current)?,
+        Command::SetVolume(volume) => return Ok(Some(Reply::Volume(api.set_volume(volume)))),
+        Command::VolumeUp => return Ok(Some(Reply::Volume(api.incr_volume()))),
+        Command::VolumeDown => return Ok(Some(Reply::Volume(api.decr_volume()))),
+        Command::TogglePlayPause => api.toggle_play_pause()?,
+        Command::Stop => api.stop()?,
+        Command::Next => api.next()?,
+        Command::Previous => api.previous()?,
+        Command::PowerOn => api.power_on()?,
+        Command::Suspend => api.suspend()?,
+    }
+    Ok(None)
+}
+
+/// Poll the streamer's state on a background thread. The thread stops when
+/// the app stops listening for events.
+pub fn poll_status(api: Api, interval: Duration, events: Sender<Event<Key>>) {
+    thread::spawn(move || loop {
+        if events
+            .send(Event::Api(Reply::Status(api.get_status())))
+            .is_err()
+        {
+            break;
+        }
+        thread::sleep(interval);
+    });
+}